@@ -41,6 +41,7 @@ import android.os.PersistableBundle;
 import android.sysprop.HypervisorProperties;
 import android.system.virtualizationservice.DiskImage;
 import android.system.virtualizationservice.Partition;
+import android.system.virtualizationservice.SharedDirectory;
 import android.system.virtualizationservice.VirtualMachineAppConfig;
 import android.system.virtualizationservice.VirtualMachinePayloadConfig;
 import android.system.virtualizationservice.VirtualMachineRawConfig;
@@ -720,6 +721,9 @@ public final class VirtualMachineConfig {
         config.cpuTopology = (byte) this.mCpuTopology;
         config.consoleInputDevice = mConsoleInputDevice;
         config.devices = EMPTY_STRING_ARRAY;
+        config.sharedDirectories = new SharedDirectory[0];
+        config.pmemFiles = new ParcelFileDescriptor[0];
+        config.deviceTreeOverlays = new ParcelFileDescriptor[0];
         config.networkSupported = this.mNetworkSupported;
         config.platformVersion = "~1.0";
         return config;
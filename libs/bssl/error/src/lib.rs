@@ -43,6 +43,12 @@ pub enum Error {
 
     /// Unimplemented operation.
     Unimplemented,
+
+    /// An integer such as an affine coordinate doesn't fit in the expected number of bytes.
+    CoordinateTooLarge,
+
+    /// An argument is outside of the range accepted by the operation.
+    InvalidArgument,
 }
 
 impl fmt::Display for Error {
@@ -57,6 +63,10 @@ impl fmt::Display for Error {
                 write!(f, "An error occurred when interacting with the coset crate")
             }
             Self::Unimplemented => write!(f, "Unimplemented operation"),
+            Self::CoordinateTooLarge => {
+                write!(f, "The integer doesn't fit in the expected number of bytes")
+            }
+            Self::InvalidArgument => write!(f, "An argument is outside of the accepted range"),
         }
     }
 }
@@ -80,6 +90,8 @@ pub enum ApiName {
     CBB_len,
     EC_GROUP_new_by_curve_name,
     EC_KEY_check_key,
+    EC_KEY_derive_from_secret,
+    EC_KEY_dup,
     EC_KEY_generate_key,
     EC_KEY_get0_group,
     EC_KEY_get0_public_key,
@@ -87,18 +99,24 @@ pub enum ApiName {
     EC_KEY_parse_private_key,
     EC_KEY_new_by_curve_name,
     EC_KEY_set_public_key_affine_coordinates,
+    EC_POINT_cmp,
     EC_POINT_get_affine_coordinates,
+    ECDH_compute_key,
     ECDSA_SIG_from_bytes,
     ECDSA_SIG_new,
     ECDSA_SIG_set0,
     ECDSA_sign,
     ECDSA_size,
     ECDSA_verify,
+    ED25519_sign,
     ED25519_verify,
     EVP_AEAD_CTX_new,
     EVP_AEAD_CTX_open,
     EVP_AEAD_CTX_seal,
     EVP_Digest,
+    EVP_DigestFinal_ex,
+    EVP_DigestInit_ex,
+    EVP_DigestUpdate,
     EVP_MD_CTX_new,
     EVP_PKEY_new,
     EVP_PKEY_new_raw_public_key,
@@ -108,7 +126,13 @@ pub enum ApiName {
     EVP_DigestVerifyInit,
     HKDF,
     HMAC,
+    HMAC_CTX_new,
+    HMAC_Final,
+    HMAC_Init_ex,
+    HMAC_Update,
     i2d_ECDSA_SIG,
+    PKCS5_PBKDF2_HMAC,
     RAND_bytes,
     SHA256,
+    X25519,
 }
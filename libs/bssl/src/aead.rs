@@ -14,20 +14,28 @@
 
 //! Wrappers of the AEAD functions in BoringSSL aead.h.
 
+use crate::ec_key::ZVec;
 use crate::util::{check_int_result, to_call_failed_error};
+use alloc::vec;
+use alloc::vec::Vec;
 use bssl_avf_error::{ApiName, Result};
 use bssl_sys::{
     EVP_AEAD_CTX_free, EVP_AEAD_CTX_new, EVP_AEAD_CTX_open, EVP_AEAD_CTX_seal,
     EVP_AEAD_max_overhead, EVP_AEAD_nonce_length, EVP_aead_aes_256_gcm,
-    EVP_aead_aes_256_gcm_randnonce, EVP_AEAD, EVP_AEAD_CTX, EVP_AEAD_DEFAULT_TAG_LENGTH,
+    EVP_aead_aes_256_gcm_randnonce, EVP_aead_chacha20_poly1305, EVP_AEAD, EVP_AEAD_CTX,
+    EVP_AEAD_DEFAULT_TAG_LENGTH,
 };
 use core::ptr::NonNull;
+use zeroize::Zeroizing;
 
 /// BoringSSL spec recommends to use 12-byte nonces.
 ///
 /// https://commondatastorage.googleapis.com/chromium-boringssl-docs/aead.h.html#EVP_aead_aes_256_gcm
 pub const AES_GCM_NONCE_LENGTH: usize = 12;
 
+/// The nonce length of ChaCha20-Poly1305 as specified in RFC 8439.
+pub const CHACHA20_POLY1305_NONCE_LENGTH: usize = 12;
+
 /// Magic value indicating that the default tag length for an AEAD should be used to
 /// initialize `AeadContext`.
 const AEAD_DEFAULT_TAG_LENGTH: usize = EVP_AEAD_DEFAULT_TAG_LENGTH as usize;
@@ -62,6 +70,18 @@ impl Aead {
         Self(unsafe { &*p })
     }
 
+    /// ChaCha20 and Poly1305 as described in RFC 8439, with 12-byte nonces.
+    ///
+    /// This is faster than AES-GCM on platforms without AES hardware acceleration.
+    pub fn chacha20_poly1305() -> Self {
+        // SAFETY: This function does not access any Rust variables and simply returns
+        // a pointer to the static variable in BoringSSL.
+        let p = unsafe { EVP_aead_chacha20_poly1305() };
+        // SAFETY: The returned pointer should always be valid and points to a static
+        // `EVP_AEAD`.
+        Self(unsafe { &*p })
+    }
+
     /// Returns the maximum number of additional bytes added by the act of sealing data.
     pub fn max_overhead(&self) -> usize {
         // SAFETY: This function only reads from self.
@@ -175,3 +195,86 @@ impl AeadContext {
         self.aead
     }
 }
+
+/// Encrypts and authenticates `plaintext` with AES-256-GCM and returns the ciphertext followed
+/// by the tag.
+pub fn aes_gcm_encrypt(
+    key: &[u8; 32],
+    nonce: &[u8; AES_GCM_NONCE_LENGTH],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>> {
+    encrypt(Aead::aes_256_gcm(), key, nonce, aad, plaintext)
+}
+
+/// Authenticates and decrypts `ciphertext` produced by [`aes_gcm_encrypt`].
+///
+/// If the tag doesn't match, the call fails with `CipherError::BadDecrypt` and no plaintext
+/// is returned.
+pub fn aes_gcm_decrypt(
+    key: &[u8; 32],
+    nonce: &[u8; AES_GCM_NONCE_LENGTH],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> Result<ZVec> {
+    decrypt(Aead::aes_256_gcm(), key, nonce, aad, ciphertext)
+}
+
+/// Encrypts and authenticates `plaintext` with ChaCha20-Poly1305 and returns the ciphertext
+/// followed by the tag.
+pub fn chacha20_poly1305_encrypt(
+    key: &[u8; 32],
+    nonce: &[u8; CHACHA20_POLY1305_NONCE_LENGTH],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>> {
+    encrypt(Aead::chacha20_poly1305(), key, nonce, aad, plaintext)
+}
+
+/// Authenticates and decrypts `ciphertext` produced by [`chacha20_poly1305_encrypt`].
+///
+/// If the tag doesn't match, the call fails with `CipherError::BadDecrypt` and no plaintext
+/// is returned.
+pub fn chacha20_poly1305_decrypt(
+    key: &[u8; 32],
+    nonce: &[u8; CHACHA20_POLY1305_NONCE_LENGTH],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> Result<ZVec> {
+    decrypt(Aead::chacha20_poly1305(), key, nonce, aad, ciphertext)
+}
+
+/// Encrypts and authenticates `plaintext` with the algorithm `aead` and returns the ciphertext
+/// followed by the tag.
+///
+/// The lengths of `key` and `nonce` must match those required by `aead`.
+pub fn encrypt(
+    aead: Aead,
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>> {
+    let aead_ctx = AeadContext::new(aead, key, None)?;
+    let mut out = vec![0u8; plaintext.len() + aead.max_overhead()];
+    let len = aead_ctx.seal(plaintext, nonce, aad, &mut out)?.len();
+    out.truncate(len);
+    Ok(out)
+}
+
+/// Authenticates and decrypts `ciphertext` produced by [`encrypt`] with the same `aead`.
+///
+/// If the tag doesn't match, the call fails with `CipherError::BadDecrypt` and no plaintext
+/// is returned.
+pub fn decrypt(
+    aead: Aead,
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> Result<ZVec> {
+    let aead_ctx = AeadContext::new(aead, key, None)?;
+    let mut out = Zeroizing::new(vec![0u8; ciphertext.len()]);
+    let plaintext = aead_ctx.open(ciphertext, nonce, aad, &mut out)?;
+    Ok(plaintext.to_vec().into())
+}
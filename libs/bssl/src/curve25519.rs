@@ -13,12 +13,22 @@
 // limitations under the License.
 
 //! Wrappers of the Curve25519 related functions in BoringSSL curve25519.h.
+//!
+//! The keys are generated from the same source of randomness as [`rand_bytes`](crate::rand_bytes).
 
+use crate::ec_key::ZVec;
 use crate::util::check_int_result;
+use alloc::vec;
 use bssl_avf_error::{ApiName, Result};
+use coset::{iana, Algorithm, CoseKey, KeyType, Label};
+use zeroize::Zeroizing;
 
+const ED25519_PRIVATE_KEY_LEN: usize = bssl_sys::ED25519_PRIVATE_KEY_LEN as usize;
 const ED25519_PUBLIC_KEY_LEN: usize = bssl_sys::ED25519_PUBLIC_KEY_LEN as usize;
 const ED25519_SIGNATURE_LEN: usize = bssl_sys::ED25519_SIGNATURE_LEN as usize;
+const X25519_PRIVATE_KEY_LEN: usize = bssl_sys::X25519_PRIVATE_KEY_LEN as usize;
+const X25519_PUBLIC_VALUE_LEN: usize = bssl_sys::X25519_PUBLIC_VALUE_LEN as usize;
+const X25519_SHARED_KEY_LEN: usize = bssl_sys::X25519_SHARED_KEY_LEN as usize;
 
 /// Verifies the signature of a message with the given ED25519 public key.
 pub fn ed25519_verify(
@@ -37,3 +47,110 @@ pub fn ed25519_verify(
     };
     check_int_result(ret, ApiName::ED25519_verify)
 }
+
+/// An Ed25519 key pair used for EdDSA signatures.
+///
+/// The private key is zeroized when the key pair is dropped.
+pub struct Ed25519 {
+    private_key: Zeroizing<[u8; ED25519_PRIVATE_KEY_LEN]>,
+    public_key: [u8; ED25519_PUBLIC_KEY_LEN],
+}
+
+impl Ed25519 {
+    /// Generates a new random Ed25519 key pair.
+    pub fn generate() -> Self {
+        let mut private_key = Zeroizing::new([0u8; ED25519_PRIVATE_KEY_LEN]);
+        let mut public_key = [0u8; ED25519_PUBLIC_KEY_LEN];
+        // SAFETY: The function only writes to the given buffers within their bounds.
+        unsafe { bssl_sys::ED25519_keypair(public_key.as_mut_ptr(), private_key.as_mut_ptr()) };
+        Self { private_key, public_key }
+    }
+
+    /// Returns the public key of the key pair.
+    pub fn public_key(&self) -> [u8; ED25519_PUBLIC_KEY_LEN] {
+        self.public_key
+    }
+
+    /// Returns the `CoseKey` for the public key, an OKP key with the EdDSA algorithm.
+    pub fn cose_public_key(&self) -> CoseKey {
+        CoseKey {
+            kty: KeyType::Assigned(iana::KeyType::OKP),
+            alg: Some(Algorithm::Assigned(iana::Algorithm::EdDSA)),
+            params: vec![
+                (
+                    Label::Int(iana::OkpKeyParameter::Crv.to_i64()),
+                    iana::EllipticCurve::Ed25519.to_i64().into(),
+                ),
+                (Label::Int(iana::OkpKeyParameter::X.to_i64()), self.public_key.to_vec().into()),
+            ],
+            ..Default::default()
+        }
+    }
+
+    /// Signs the `message` with the private key.
+    pub fn sign(&self, message: &[u8]) -> Result<[u8; ED25519_SIGNATURE_LEN]> {
+        let mut signature = [0u8; ED25519_SIGNATURE_LEN];
+        // SAFETY: The function only reads the message and the private key, and writes to
+        // `signature`, all within their bounds.
+        let ret = unsafe {
+            bssl_sys::ED25519_sign(
+                signature.as_mut_ptr(),
+                message.as_ptr(),
+                message.len(),
+                self.private_key.as_ptr(),
+            )
+        };
+        check_int_result(ret, ApiName::ED25519_sign)?;
+        Ok(signature)
+    }
+
+    /// Verifies the `signature` of the `message` with the given Ed25519 `public_key`.
+    pub fn verify(
+        public_key: &[u8; ED25519_PUBLIC_KEY_LEN],
+        message: &[u8],
+        signature: &[u8; ED25519_SIGNATURE_LEN],
+    ) -> Result<()> {
+        ed25519_verify(message, signature, public_key)
+    }
+}
+
+/// An X25519 key pair used for Diffie-Hellman key agreement.
+///
+/// The private key is zeroized when the key pair is dropped.
+pub struct X25519 {
+    private_key: Zeroizing<[u8; X25519_PRIVATE_KEY_LEN]>,
+    public_key: [u8; X25519_PUBLIC_VALUE_LEN],
+}
+
+impl X25519 {
+    /// Generates a new random X25519 key pair.
+    pub fn generate() -> Self {
+        let mut private_key = Zeroizing::new([0u8; X25519_PRIVATE_KEY_LEN]);
+        let mut public_key = [0u8; X25519_PUBLIC_VALUE_LEN];
+        // SAFETY: The function only writes to the given buffers within their bounds.
+        unsafe { bssl_sys::X25519_keypair(public_key.as_mut_ptr(), private_key.as_mut_ptr()) };
+        Self { private_key, public_key }
+    }
+
+    /// Returns the public value of the key pair, to be sent to the peer.
+    pub fn public_key(&self) -> [u8; X25519_PUBLIC_VALUE_LEN] {
+        self.public_key
+    }
+
+    /// Computes the shared secret with the public value `peer` of the other party.
+    ///
+    /// The call fails if `peer` is a point of small order, as the secret would be all zeros.
+    pub fn agree(&self, peer: &[u8; X25519_PUBLIC_VALUE_LEN]) -> Result<ZVec> {
+        let mut secret = ZVec::from(vec![0u8; X25519_SHARED_KEY_LEN]);
+        // SAFETY: The function only reads the keys and writes to `secret` within their bounds.
+        let ret = unsafe {
+            bssl_sys::X25519(
+                secret.as_mut_slice().as_mut_ptr(),
+                self.private_key.as_ptr(),
+                peer.as_ptr(),
+            )
+        };
+        check_int_result(ret, ApiName::X25519)?;
+        Ok(secret)
+    }
+}
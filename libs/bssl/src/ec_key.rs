@@ -17,19 +17,23 @@
 
 use crate::cbb::CbbFixed;
 use crate::cbs::Cbs;
+use crate::digest::Digester;
+use crate::evp::PKey;
+use crate::sha::sha256;
 use crate::util::{check_int_result, to_call_failed_error};
 use alloc::vec;
 use alloc::vec::Vec;
 use bssl_avf_error::{ApiName, Error, Result};
 use bssl_sys::{
-    i2d_ECDSA_SIG, BN_bin2bn, BN_bn2bin_padded, BN_clear_free, BN_new, CBB_flush, CBB_len,
-    ECDSA_SIG_free, ECDSA_SIG_from_bytes, ECDSA_SIG_get0_r, ECDSA_SIG_get0_s, ECDSA_SIG_new,
-    ECDSA_SIG_set0, ECDSA_sign, ECDSA_size, ECDSA_verify, EC_GROUP_get_curve_name,
-    EC_GROUP_new_by_curve_name, EC_KEY_check_key, EC_KEY_free, EC_KEY_generate_key,
-    EC_KEY_get0_group, EC_KEY_get0_public_key, EC_KEY_marshal_private_key,
-    EC_KEY_new_by_curve_name, EC_KEY_parse_private_key, EC_KEY_set_public_key_affine_coordinates,
-    EC_POINT_get_affine_coordinates, NID_X9_62_prime256v1, NID_secp384r1, BIGNUM, ECDSA_SIG,
-    EC_GROUP, EC_KEY, EC_POINT,
+    i2d_ECDSA_SIG, BN_bin2bn, BN_bn2bin_padded, BN_clear_free, BN_new, BN_num_bytes, CBB_flush,
+    CBB_len, ECDH_compute_key, ECDSA_SIG_free, ECDSA_SIG_from_bytes, ECDSA_SIG_get0_r,
+    ECDSA_SIG_get0_s, ECDSA_SIG_new, ECDSA_SIG_set0, ECDSA_sign, ECDSA_size, ECDSA_verify,
+    EC_GROUP_get_curve_name, EC_GROUP_new_by_curve_name, EC_KEY_check_key,
+    EC_KEY_derive_from_secret, EC_KEY_dup, EC_KEY_free, EC_KEY_generate_key, EC_KEY_get0_group,
+    EC_KEY_get0_public_key, EC_KEY_marshal_private_key, EC_KEY_new_by_curve_name,
+    EC_KEY_parse_private_key, EC_KEY_set_public_key_affine_coordinates, EC_POINT_cmp,
+    EC_POINT_get_affine_coordinates, NID_X9_62_prime256v1, NID_secp384r1, NID_secp521r1, BIGNUM,
+    ECDSA_SIG, EC_GROUP, EC_KEY, EC_POINT,
 };
 use cbor_util::{get_label_value, get_label_value_as_bytes};
 use ciborium::Value;
@@ -37,16 +41,23 @@ use core::mem;
 use core::ptr::{self, NonNull};
 use coset::{
     iana::{self, EnumI64},
-    CborSerializable, CoseKey, CoseKeyBuilder, KeyType, Label,
+    CborSerializable, CoseKey, CoseKeyBuilder, CoseSign1Builder, HeaderBuilder, KeyType, Label,
 };
 use log::error;
 use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
-const ES256_ALGO: iana::Algorithm = iana::Algorithm::ES256;
 const P256_CURVE: iana::EllipticCurve = iana::EllipticCurve::P_256;
 const P384_CURVE: iana::EllipticCurve = iana::EllipticCurve::P_384;
+const P521_CURVE: iana::EllipticCurve = iana::EllipticCurve::P_521;
 const P256_AFFINE_COORDINATE_SIZE: usize = 32;
 const P384_AFFINE_COORDINATE_SIZE: usize = 48;
+const P521_AFFINE_COORDINATE_SIZE: usize = 66;
+const P256_UNCOMPRESSED_POINT_SIZE: usize = 1 + 2 * P256_AFFINE_COORDINATE_SIZE;
+const P256_COMPRESSED_POINT_SIZE: usize = 1 + P256_AFFINE_COORDINATE_SIZE;
+/// The prefix of an uncompressed point in SEC1 encoding.
+const UNCOMPRESSED_POINT_PREFIX: u8 = 0x04;
+const COMPRESSED_EVEN_POINT_PREFIX: u8 = 0x02;
+const COMPRESSED_ODD_POINT_PREFIX: u8 = 0x03;
 
 /// Wrapper of an `EC_KEY` object, representing a public or private EC key.
 pub struct EcKey(pub(crate) NonNull<EC_KEY>);
@@ -82,6 +93,49 @@ impl EcKey {
             .ok_or_else(|| to_call_failed_error(ApiName::EC_KEY_new_by_curve_name))
     }
 
+    /// Creates a new EC P-521 key pair.
+    pub fn new_p521() -> Result<Self> {
+        // SAFETY: The returned pointer is checked below.
+        let ec_key = unsafe {
+            EC_KEY_new_by_curve_name(NID_secp521r1) // EC P-521 CURVE Nid
+        };
+        NonNull::new(ec_key)
+            .map(Self)
+            .ok_or_else(|| to_call_failed_error(ApiName::EC_KEY_new_by_curve_name))
+    }
+
+    /// Deterministically derives an EC P-256 key pair from `seed`, so that the same seed always
+    /// results in the same key.
+    ///
+    /// The seed must have enough entropy to be used as a private key, e.g. it can be a secret
+    /// derived from the DICE chain of the VM.
+    pub fn derive_from_seed(seed: &[u8]) -> Result<Self> {
+        // SAFETY: This function only returns a pointer to a static object, and the
+        // return is checked below.
+        let ec_group = unsafe {
+            EC_GROUP_new_by_curve_name(NID_X9_62_prime256v1) // EC P-256 CURVE Nid
+        };
+        if ec_group.is_null() {
+            return Err(to_call_failed_error(ApiName::EC_GROUP_new_by_curve_name));
+        }
+        // SAFETY: The function only reads `seed` within its bounds and the static `EC_GROUP`,
+        // and the returned pointer is checked below.
+        let ec_key = unsafe { EC_KEY_derive_from_secret(ec_group, seed.as_ptr(), seed.len()) };
+        NonNull::new(ec_key)
+            .map(Self)
+            .ok_or_else(|| to_call_failed_error(ApiName::EC_KEY_derive_from_secret))
+    }
+
+    /// Returns a deep copy of the current `EcKey`, including its private key if any.
+    ///
+    /// The copy owns its own `EC_KEY`, so either instance can be dropped independently.
+    pub fn try_clone(&self) -> Result<Self> {
+        // SAFETY: The function only reads the `EC_KEY` that has been initialized and checked
+        // non-null when this instance is created, and the returned pointer is checked below.
+        let ec_key = unsafe { EC_KEY_dup(self.0.as_ptr()) };
+        NonNull::new(ec_key).map(Self).ok_or_else(|| to_call_failed_error(ApiName::EC_KEY_dup))
+    }
+
     /// Constructs an `EcKey` instance from the provided COSE_Key encoded public key slice.
     pub fn from_cose_public_key_slice(cose_key: &[u8]) -> Result<Self> {
         let cose_key = CoseKey::from_slice(cose_key).map_err(|e| {
@@ -96,7 +150,11 @@ impl EcKey {
     /// The lifetime of the returned `EcKey` is not tied to the lifetime of the `cose_key`,
     /// because the affine coordinates stored in the `cose_key` are copied into the `EcKey`.
     ///
-    /// Currently, only the EC P-256 and P-384 curves are supported.
+    /// Currently, only the EC P-256, P-384 and P-521 curves are supported.
+    ///
+    /// Returns `Error::CoseKeyDecodingFailed` if the coordinates are missing or don't have the
+    /// size of the curve, and a `EcError::PointIsNotOnCurve` failure if they don't describe a
+    /// point on the curve.
     pub fn from_cose_public_key(cose_key: &CoseKey) -> Result<Self> {
         if cose_key.kty != KeyType::Assigned(iana::KeyType::EC2) {
             error!("Only EC2 keys are supported. Key type in the COSE Key: {:?}", cose_key.kty);
@@ -106,9 +164,10 @@ impl EcKey {
             match get_label_value(cose_key, Label::Int(iana::Ec2KeyParameter::Crv.to_i64()))? {
                 crv if crv == &Value::from(P256_CURVE.to_i64()) => EcKey::new_p256()?,
                 crv if crv == &Value::from(P384_CURVE.to_i64()) => EcKey::new_p384()?,
+                crv if crv == &Value::from(P521_CURVE.to_i64()) => EcKey::new_p521()?,
                 crv => {
                     error!(
-                        "Only EC P-256 and P-384 curves are supported. \
+                        "Only EC P-256, P-384 and P-521 curves are supported. \
                          Curve type in the COSE Key: {crv:?}"
                     );
                     return Err(Error::Unimplemented);
@@ -121,8 +180,8 @@ impl EcKey {
         group.check_affine_coordinate_size(x)?;
         group.check_affine_coordinate_size(y)?;
 
-        let x = BigNum::from_slice(x)?;
-        let y = BigNum::from_slice(y)?;
+        let x = BigNum::from_be_bytes(x)?;
+        let y = BigNum::from_be_bytes(y)?;
 
         // SAFETY: All the parameters are checked non-null and initialized.
         // The function only reads the coordinates x and y within their bounds.
@@ -171,7 +230,7 @@ impl EcKey {
     ///
     /// Returns Ok(()) if the verification succeeds, otherwise an error will be returned.
     pub fn ecdsa_verify_cose(&self, signature: &[u8], digest: &[u8]) -> Result<()> {
-        let signature = ec_cose_signature_to_der(signature)?;
+        let signature = ecdsa_raw_to_der(signature)?;
         self.ecdsa_verify_der(&signature, digest)
     }
 
@@ -211,7 +270,99 @@ impl EcKey {
     pub fn ecdsa_sign_cose(&self, digest: &[u8]) -> Result<Vec<u8>> {
         let signature = self.ecdsa_sign_der(digest)?;
         let coord_bytes = self.ec_group()?.affine_coordinate_size()?;
-        ec_der_signature_to_cose(&signature, coord_bytes)
+        ecdsa_der_to_raw(&signature, coord_bytes)
+    }
+
+    /// Signs the SHA-256 digest of `message` with the current `EcKey` using ECDSA.
+    ///
+    /// Returns the DER-encoded ECDSA signature.
+    pub fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        self.ecdsa_sign_der(&sha256(message)?)
+    }
+
+    /// Verifies the DER-encoded ECDSA `signature` of the SHA-256 digest of `message` with the
+    /// current `EcKey`.
+    ///
+    /// Returns Ok(()) if the verification succeeds, otherwise an error will be returned.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        self.ecdsa_verify_der(signature, &sha256(message)?)
+    }
+
+    /// Computes the ECDH shared secret of the private key of the current `EcKey` and the public
+    /// key `peer`, which must be on the same curve.
+    ///
+    /// The peer public key is checked to be on the curve before it is used.
+    pub fn ecdh(&self, peer: &CoseKey) -> Result<ZVec> {
+        let peer = EcKey::from_cose_public_key(peer)?;
+        let ec_group = self.ec_group()?;
+        if peer.ec_group()?.curve_nid() != ec_group.curve_nid() {
+            error!("The peer public key is on a different curve from the private key");
+            return Err(Error::CoseKeyDecodingFailed);
+        }
+        let mut secret = vec![0u8; ec_group.affine_coordinate_size()?];
+        // SAFETY: This function only writes to `secret` within its bounds, and only reads the
+        // public key point owned by `peer` and the `EC_KEY` of the current instance, which have
+        // both been initialized and checked non-null. No KDF is passed.
+        let ret = unsafe {
+            ECDH_compute_key(
+                secret.as_mut_ptr().cast(),
+                secret.len(),
+                peer.public_key_ec_point()?,
+                self.0.as_ptr(),
+                None,
+            )
+        };
+        let secret = ZVec::from(secret);
+        if usize::try_from(ret).ok() == Some(secret.as_slice().len()) {
+            Ok(secret)
+        } else {
+            Err(to_call_failed_error(ApiName::ECDH_compute_key))
+        }
+    }
+
+    /// Returns whether the public key of the current `EcKey` is the one held by `other`.
+    ///
+    /// Keys on different curves don't match. An error is only returned if `other` cannot be
+    /// decoded as an EC2 public key.
+    pub fn public_key_eq(&self, other: &CoseKey) -> Result<bool> {
+        let other = EcKey::from_cose_public_key(other)?;
+        let ec_group = self.ec_group()?;
+        if other.ec_group()?.curve_nid() != ec_group.curve_nid() {
+            return Ok(false);
+        }
+        let ctx = ptr::null_mut();
+        // SAFETY: All the parameters are checked non-null and initialized, and the two points
+        // are on the curve of `ec_group`. The last parameter `ctx` is generated when needed
+        // inside the function.
+        let ret = unsafe {
+            EC_POINT_cmp(
+                ec_group.as_ref(),
+                self.public_key_ec_point()?,
+                other.public_key_ec_point()?,
+                ctx,
+            )
+        };
+        match ret {
+            0 => Ok(true),
+            1 => Ok(false),
+            _ => Err(to_call_failed_error(ApiName::EC_POINT_cmp)),
+        }
+    }
+
+    /// Signs `payload` with the current `EcKey` and returns the serialized COSE_Sign1 structure.
+    ///
+    /// The protected header holds the ECDSA algorithm of the curve, and `aad` is passed as the
+    /// external additional authenticated data of the Sig_structure, see RFC 9052 s4.4.
+    pub fn sign_cose_sign1(&self, payload: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let ec_group = self.ec_group()?;
+        let digester = ec_group.digester()?;
+        let protected = HeaderBuilder::new().algorithm(ec_group.coset_algorithm()?).build();
+        let cose_sign1 = CoseSign1Builder::new()
+            .protected(protected)
+            .payload(payload.to_vec())
+            .try_create_signature(aad, |message| self.ecdsa_sign_cose(&digester.digest(message)?))?
+            .build();
+        Ok(cose_sign1.to_vec()?)
     }
 
     /// Returns the maximum size of an ECDSA signature using the current `EcKey`.
@@ -237,15 +388,73 @@ impl EcKey {
     }
 
     /// Returns the `CoseKey` for the public key.
+    ///
+    /// The algorithm of the key is ES256, ES384 or ES512, depending on the curve.
     pub fn cose_public_key(&self) -> Result<CoseKey> {
         let (x, y) = self.public_key_coordinates()?;
-        let curve = self.ec_group()?.coset_curve()?;
-        let key = CoseKeyBuilder::new_ec2_pub_key(curve, x, y).algorithm(ES256_ALGO).build();
+        let ec_group = self.ec_group()?;
+        let curve = ec_group.coset_curve()?;
+        let algorithm = ec_group.coset_algorithm()?;
+        let (x, y) = (x.as_slice().to_vec(), y.as_slice().to_vec());
+        let key = CoseKeyBuilder::new_ec2_pub_key(curve, x, y).algorithm(algorithm).build();
         Ok(key)
     }
 
+    /// Returns the `CoseKey` for the public key with the key identifier `kid`, so that the key
+    /// can be told apart from other keys held by the same party.
+    ///
+    /// The key is otherwise the same as the one returned by `cose_public_key`.
+    pub fn cose_public_key_with_kid(&self, kid: &[u8]) -> Result<CoseKey> {
+        let mut key = self.cose_public_key()?;
+        key.key_id = kid.to_vec();
+        Ok(key)
+    }
+
+    /// Returns the DER-encoded SubjectPublicKeyInfo structure of the public key, as specified in
+    /// RFC 5280 s4.1.2.7, with the algorithm and named curve OIDs of RFC 5480.
+    pub fn public_key_spki_der(&self) -> Result<Vec<u8>> {
+        // Only the public key is copied into the `PKey`, so it never holds the private key.
+        let public_key = EcKey::from_cose_public_key(&self.cose_public_key()?)?;
+        PKey::try_from(public_key)?.subject_public_key_info()
+    }
+
+    /// Returns the public key as an uncompressed point (0x04 | X | Y) in the SEC1 encoding.
+    ///
+    /// Currently, only the EC P-256 curve is supported.
+    pub fn public_key_point(&self) -> Result<[u8; P256_UNCOMPRESSED_POINT_SIZE]> {
+        let (x, y) = self.public_key_coordinates()?;
+        if x.len() != P256_AFFINE_COORDINATE_SIZE {
+            error!("Only the EC P-256 curve is supported. Coordinate size: {}", x.len());
+            return Err(Error::Unimplemented);
+        }
+        let mut point = [0u8; P256_UNCOMPRESSED_POINT_SIZE];
+        point[0] = UNCOMPRESSED_POINT_PREFIX;
+        let (x, y) = (x.as_slice(), y.as_slice());
+        point[1..(1 + x.len())].copy_from_slice(x);
+        point[(1 + x.len())..].copy_from_slice(y);
+        Ok(point)
+    }
+
+    /// Returns the public key as a compressed point (0x02 or 0x03 | X) in the SEC1 encoding,
+    /// where the prefix holds the parity of Y.
+    ///
+    /// Currently, only the EC P-256 curve is supported.
+    pub fn public_key_point_compressed(&self) -> Result<[u8; P256_COMPRESSED_POINT_SIZE]> {
+        let (x, y) = self.public_key_coordinates()?;
+        if x.len() != P256_AFFINE_COORDINATE_SIZE {
+            error!("Only the EC P-256 curve is supported. Coordinate size: {}", x.len());
+            return Err(Error::Unimplemented);
+        }
+        let y_is_odd = y.as_slice().last().is_some_and(|b| b & 1 == 1);
+        let mut point = [0u8; P256_COMPRESSED_POINT_SIZE];
+        point[0] =
+            if y_is_odd { COMPRESSED_ODD_POINT_PREFIX } else { COMPRESSED_EVEN_POINT_PREFIX };
+        point[1..].copy_from_slice(x.as_slice());
+        Ok(point)
+    }
+
     /// Returns the x and y coordinates of the public key.
-    fn public_key_coordinates(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+    fn public_key_coordinates(&self) -> Result<(ZVec, ZVec)> {
         let ec_group = self.ec_group()?;
         let ec_point = self.public_key_ec_point()?;
         let mut x = BigNum::new()?;
@@ -264,7 +473,7 @@ impl EcKey {
         };
         check_int_result(ret, ApiName::EC_POINT_get_affine_coordinates)?;
         let len = ec_group.affine_coordinate_size()?;
-        Ok((x.to_padded_vec(len)?, y.to_padded_vec(len)?))
+        Ok((x.to_padded_zvec(len)?, y.to_padded_zvec(len)?))
     }
 
     /// Returns a pointer to the public key point inside `EC_KEY`. The memory region pointed
@@ -341,19 +550,26 @@ impl EcKey {
         // SAFETY: This is safe because the CBB pointer is initialized with `CBB_init_fixed()`,
         // and it has been flushed, thus it has no active children.
         let len = unsafe { CBB_len(cbb.as_ref()) };
-        Ok(buf.get(0..len).ok_or_else(|| to_call_failed_error(ApiName::CBB_len))?.to_vec().into())
+        let encoded = buf.get(0..len).ok_or_else(|| to_call_failed_error(ApiName::CBB_len))?;
+        // The private key is only ever written to `buf`, which is zeroized when it goes out of
+        // scope on every path, and to the returned `ZVec`, which is allocated with its final
+        // size so that its buffer is never reallocated and is zeroized when dropped.
+        let mut private_key = ZVec::from(vec![0u8; len]);
+        private_key.as_mut_slice().copy_from_slice(encoded);
+        Ok(private_key)
     }
 }
 
-/// Convert a COSE format (R | S) ECDSA signature to a DER-encoded form.
-fn ec_cose_signature_to_der(signature: &[u8]) -> Result<Vec<u8>> {
+/// Converts a raw (R | S) ECDSA signature, as used by COSE, to a DER-encoded ECDSA-Sig-Value.
+pub fn ecdsa_raw_to_der(signature: &[u8]) -> Result<Vec<u8>> {
     let mut ec_sig = EcSignature::new()?;
     ec_sig.load_from_cose(signature)?;
     ec_sig.to_der()
 }
 
-/// Convert a DER-encoded signature to COSE format (R | S).
-fn ec_der_signature_to_cose(signature: &[u8], coord_bytes: usize) -> Result<Vec<u8>> {
+/// Converts a DER-encoded ECDSA-Sig-Value to the raw (R | S) form used by COSE, where R and S
+/// are each zero-padded to `coord_bytes` bytes.
+pub fn ecdsa_der_to_raw(signature: &[u8], coord_bytes: usize) -> Result<Vec<u8>> {
     let ec_sig = EcSignature::new_from_der(signature)?;
     ec_sig.to_cose(coord_bytes)
 }
@@ -378,8 +594,8 @@ impl EcSignature {
         if signature.len() != 2 * coord_bytes {
             return Err(Error::InternalError);
         }
-        let mut r = BigNum::from_slice(&signature[..coord_bytes])?;
-        let mut s = BigNum::from_slice(&signature[coord_bytes..])?;
+        let mut r = BigNum::from_be_bytes(&signature[..coord_bytes])?;
+        let mut s = BigNum::from_be_bytes(&signature[coord_bytes..])?;
 
         check_int_result(
             // SAFETY: The ECDSA_SIG was properly allocated and not yet freed. We have ownership
@@ -484,6 +700,7 @@ impl<'a> EcGroup<'a> {
         match self.curve_nid() {
             NID_X9_62_prime256v1 => Ok(P256_CURVE),
             NID_secp384r1 => Ok(P384_CURVE),
+            NID_secp521r1 => Ok(P521_CURVE),
             name => {
                 error!("Unsupported curve NID: {}", name);
                 Err(Error::Unimplemented)
@@ -491,11 +708,35 @@ impl<'a> EcGroup<'a> {
         }
     }
 
+    /// Returns the ECDSA algorithm which uses the curve, as defined in RFC 9053 s2.1.
+    fn coset_algorithm(&self) -> Result<iana::Algorithm> {
+        #[allow(non_upper_case_globals)]
+        match self.curve_nid() {
+            NID_X9_62_prime256v1 => Ok(iana::Algorithm::ES256),
+            NID_secp384r1 => Ok(iana::Algorithm::ES384),
+            NID_secp521r1 => Ok(iana::Algorithm::ES512),
+            name => {
+                error!("Unsupported curve NID: {}", name);
+                Err(Error::Unimplemented)
+            }
+        }
+    }
+
+    /// Returns the digester of the ECDSA algorithm which uses the curve.
+    fn digester(&self) -> Result<Digester> {
+        match self.coset_algorithm()? {
+            iana::Algorithm::ES256 => Ok(Digester::sha256()),
+            iana::Algorithm::ES384 => Ok(Digester::sha384()),
+            _ => Ok(Digester::sha512()),
+        }
+    }
+
     fn affine_coordinate_size(&self) -> Result<usize> {
         #[allow(non_upper_case_globals)]
         match self.curve_nid() {
             NID_X9_62_prime256v1 => Ok(P256_AFFINE_COORDINATE_SIZE),
             NID_secp384r1 => Ok(P384_AFFINE_COORDINATE_SIZE),
+            NID_secp521r1 => Ok(P521_AFFINE_COORDINATE_SIZE),
             name => {
                 error!("Unsupported curve NID: {}", name);
                 Err(Error::Unimplemented)
@@ -525,6 +766,9 @@ impl<'a> AsRef<EC_GROUP> for EcGroup<'a> {
 }
 
 /// A u8 vector that is zeroed when dropped.
+///
+/// The vector cannot grow, so its buffer is never reallocated and no copy of its content is
+/// left behind in freed memory.
 #[derive(Zeroize, ZeroizeOnDrop)]
 pub struct ZVec(Vec<u8>);
 
@@ -533,6 +777,11 @@ impl ZVec {
     pub fn as_slice(&self) -> &[u8] {
         &self.0[..]
     }
+
+    /// Extracts a mutable slice containing the entire vector.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.0[..]
+    }
 }
 
 impl From<Vec<u8>> for ZVec {
@@ -541,17 +790,22 @@ impl From<Vec<u8>> for ZVec {
     }
 }
 
+/// Wrapper of a `BIGNUM`, which is erased when dropped so that it can hold private scalars.
 struct BigNum(NonNull<BIGNUM>);
 
 impl Drop for BigNum {
     fn drop(&mut self) {
-        // SAFETY: The pointer has been created with `BN_new`.
+        // SAFETY: The pointer has been created with `BN_new` or `BN_bin2bn`.
         unsafe { BN_clear_free(self.as_mut_ptr()) }
     }
 }
 
 impl BigNum {
-    fn from_slice(x: &[u8]) -> Result<Self> {
+    /// Creates a `BigNum` from the big-endian integer `x`.
+    ///
+    /// BoringSSL copies `x` into the `BIGNUM` without any intermediate buffer, so the only
+    /// copy of a secret `x` made here is erased when the `BigNum` is dropped.
+    fn from_be_bytes(x: &[u8]) -> Result<Self> {
         // SAFETY: The function reads `x` within its bounds, and the returned
         // pointer is checked below.
         let bn = unsafe { BN_bin2bn(x.as_ptr(), x.len(), ptr::null_mut()) };
@@ -565,11 +819,23 @@ impl BigNum {
     }
 
     /// Converts the `BigNum` to a big-endian integer. The integer is padded with leading zeros up
-    /// to size `len`. The conversion fails if `len` is smaller than the size of the integer.
-    fn to_padded_vec(&self, len: usize) -> Result<Vec<u8>> {
-        let mut num = vec![0u8; len];
-        // SAFETY: The `BIGNUM` pointer has been created with `BN_new`.
-        let ret = unsafe { BN_bn2bin_padded(num.as_mut_ptr(), num.len(), self.0.as_ptr()) };
+    /// to size `len`. The conversion fails with `Error::CoordinateTooLarge` if `len` is smaller
+    /// than the size of the integer.
+    ///
+    /// The output is zeroized when dropped, including if the conversion fails.
+    fn to_padded_zvec(&self, len: usize) -> Result<ZVec> {
+        // SAFETY: The function only reads the `BIGNUM`, which has been checked non-null when
+        // this instance was created.
+        let num_bytes = unsafe { BN_num_bytes(self.0.as_ptr()) };
+        if !usize::try_from(num_bytes).is_ok_and(|n| n <= len) {
+            error!("The integer of {num_bytes} bytes doesn't fit in {len} bytes");
+            return Err(Error::CoordinateTooLarge);
+        }
+        let mut num = ZVec::from(vec![0u8; len]);
+        let out = num.as_mut_slice();
+        // SAFETY: The function only writes to `out` within its bounds, and the `BIGNUM` pointer
+        // has been checked non-null when this instance was created.
+        let ret = unsafe { BN_bn2bin_padded(out.as_mut_ptr(), out.len(), self.0.as_ptr()) };
         check_int_result(ret, ApiName::BN_bn2bin_padded)?;
         Ok(num)
     }
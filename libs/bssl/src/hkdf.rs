@@ -15,7 +15,9 @@
 //! Wrappers of the HKDF functions in BoringSSL hkdf.h.
 
 use crate::digest::Digester;
+use crate::ec_key::ZVec;
 use crate::util::check_int_result;
+use alloc::vec;
 use bssl_avf_error::{ApiName, Result};
 use bssl_sys::HKDF;
 use zeroize::Zeroizing;
@@ -31,11 +33,32 @@ pub fn hkdf<const N: usize>(
     digester: Digester,
 ) -> Result<Zeroizing<[u8; N]>> {
     let mut key = Zeroizing::new([0u8; N]);
+    hkdf_into(&mut key[..], secret, salt, info, digester)?;
+    Ok(key)
+}
+
+/// Computes HKDF-SHA256 (as specified by [RFC 5869]) of initial keying material `secret` with
+/// `salt` and `info`, and returns `out_len` bytes of output keying material.
+///
+/// [RFC 5869]: https://www.rfc-editor.org/rfc/rfc5869.html
+pub fn hkdf_sha256(secret: &[u8], salt: &[u8], info: &[u8], out_len: usize) -> Result<ZVec> {
+    let mut key = ZVec::from(vec![0u8; out_len]);
+    hkdf_into(key.as_mut_slice(), secret, salt, info, Digester::sha256())?;
+    Ok(key)
+}
+
+fn hkdf_into(
+    out: &mut [u8],
+    secret: &[u8],
+    salt: &[u8],
+    info: &[u8],
+    digester: Digester,
+) -> Result<()> {
     // SAFETY: Only reads from/writes to the provided slices and the digester was non-null.
     let ret = unsafe {
         HKDF(
-            key.as_mut_ptr(),
-            key.len(),
+            out.as_mut_ptr(),
+            out.len(),
             digester.0,
             secret.as_ptr(),
             secret.len(),
@@ -45,6 +68,5 @@ pub fn hkdf<const N: usize>(
             info.len(),
         )
     };
-    check_int_result(ret, ApiName::HKDF)?;
-    Ok(key)
+    check_int_result(ret, ApiName::HKDF)
 }
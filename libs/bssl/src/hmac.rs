@@ -16,9 +16,14 @@
 
 use crate::digest::Digester;
 use crate::sha::SHA256_DIGEST_LENGTH;
-use crate::util::to_call_failed_error;
+use crate::util::{check_int_result, to_call_failed_error};
+use alloc::vec;
+use alloc::vec::Vec;
 use bssl_avf_error::{ApiName, Result};
-use bssl_sys::HMAC;
+use bssl_sys::{
+    HMAC_CTX_free, HMAC_CTX_new, HMAC_Final, HMAC_Init_ex, HMAC_Update, HMAC, HMAC_CTX,
+};
+use core::ptr::{self, NonNull};
 
 /// Computes the HMAC using SHA-256 for the given `data` with the given `key`.
 pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<[u8; SHA256_DIGEST_LENGTH]> {
@@ -56,3 +61,69 @@ fn hmac<const HASH_LEN: usize>(
         Err(to_call_failed_error(ApiName::HMAC))
     }
 }
+
+/// Incremental HMAC computation wrapping `HMAC_CTX`.
+pub struct HmacCtx {
+    ctx: NonNull<HMAC_CTX>,
+    digester: Digester,
+}
+
+impl Drop for HmacCtx {
+    fn drop(&mut self) {
+        // SAFETY: It is safe because the pointer has been created with `HMAC_CTX_new`
+        // and isn't used after this. This also erases the key material held by the context.
+        unsafe { HMAC_CTX_free(self.ctx.as_ptr()) }
+    }
+}
+
+impl HmacCtx {
+    /// Creates a new `HmacCtx` computing the HMAC with the given `key` and `digester`.
+    pub fn new(key: &[u8], digester: Digester) -> Result<Self> {
+        // SAFETY: The returned pointer is checked below.
+        let ctx = unsafe { HMAC_CTX_new() };
+        let ctx = NonNull::new(ctx).ok_or_else(|| to_call_failed_error(ApiName::HMAC_CTX_new))?;
+        let hmac_ctx = Self { ctx, digester };
+        let engine = ptr::null_mut(); // Use the default engine.
+
+        // SAFETY: Only reads from the provided slice, the context is valid and the digester
+        // points to a static `EVP_MD`.
+        let ret = unsafe {
+            HMAC_Init_ex(
+                hmac_ctx.ctx.as_ptr(),
+                key.as_ptr() as *const _,
+                key.len(),
+                hmac_ctx.digester.0,
+                engine,
+            )
+        };
+        check_int_result(ret, ApiName::HMAC_Init_ex)?;
+        Ok(hmac_ctx)
+    }
+
+    /// Creates a new `HmacCtx` computing the HMAC using SHA-256 with the given `key`.
+    pub fn sha256(key: &[u8]) -> Result<Self> {
+        Self::new(key, Digester::sha256())
+    }
+
+    /// Appends `data` to the message being authenticated.
+    pub fn update(&mut self, data: &[u8]) -> Result<()> {
+        // SAFETY: Only reads from the provided slice and the context has been initialized.
+        let ret = unsafe { HMAC_Update(self.ctx.as_ptr(), data.as_ptr(), data.len()) };
+        check_int_result(ret, ApiName::HMAC_Update)
+    }
+
+    /// Completes the computation and returns the HMAC of all the data passed to `update`.
+    pub fn finalize(self) -> Result<Vec<u8>> {
+        let mut out = vec![0u8; self.digester.size()];
+        let mut out_len = 0;
+        // SAFETY: Only writes to `out`, which has room for the digest of the digester the
+        // context has been initialized with.
+        let ret = unsafe { HMAC_Final(self.ctx.as_ptr(), out.as_mut_ptr(), &mut out_len) };
+        check_int_result(ret, ApiName::HMAC_Final)?;
+        if out_len == (out.len() as u32) {
+            Ok(out)
+        } else {
+            Err(to_call_failed_error(ApiName::HMAC_Final))
+        }
+    }
+}
@@ -29,20 +29,26 @@ mod err;
 mod evp;
 mod hkdf;
 mod hmac;
+mod pbkdf2;
 mod rand;
 mod sha;
 mod util;
 
 pub use bssl_avf_error::{ApiName, CipherError, EcError, EcdsaError, Error, ReasonCode, Result};
 
-pub use aead::{Aead, AeadContext, AES_GCM_NONCE_LENGTH};
+pub use aead::{
+    aes_gcm_decrypt, aes_gcm_encrypt, chacha20_poly1305_decrypt, chacha20_poly1305_encrypt,
+    decrypt, encrypt, Aead, AeadContext, AES_GCM_NONCE_LENGTH, CHACHA20_POLY1305_NONCE_LENGTH,
+};
 pub use cbb::CbbFixed;
 pub use cbs::Cbs;
-pub use curve25519::ed25519_verify;
+pub use curve25519::{ed25519_verify, Ed25519, X25519};
 pub use digest::Digester;
-pub use ec_key::{EcKey, ZVec};
+pub use ec_key::{ecdsa_der_to_raw, ecdsa_raw_to_der, EcKey, ZVec};
 pub use evp::{PKey, PKeyType};
-pub use hkdf::hkdf;
-pub use hmac::hmac_sha256;
+pub use hkdf::{hkdf, hkdf_sha256};
+pub use hmac::{hmac_sha256, HmacCtx};
+pub use pbkdf2::pbkdf2_sha256;
 pub use rand::rand_bytes;
-pub use sha::sha256;
+pub use sha::{sha256, sha384, sha512, Sha256};
+pub use util::ct_eq;
@@ -0,0 +1,57 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wrappers of the PBKDF2 functions in BoringSSL evp.h.
+
+use crate::digest::Digester;
+use crate::ec_key::ZVec;
+use crate::util::check_int_result;
+use alloc::vec;
+use bssl_avf_error::{ApiName, Error, Result};
+use bssl_sys::PKCS5_PBKDF2_HMAC;
+
+/// Computes PBKDF2 (as specified by [RFC 8018]) with HMAC-SHA256 of `password` with `salt`
+/// and the given number of `iterations`, and returns `out_len` bytes of derived key.
+///
+/// Fails with `Error::InvalidArgument` if `iterations` is zero.
+///
+/// [RFC 8018]: https://www.rfc-editor.org/rfc/rfc8018.html#section-5.2
+pub fn pbkdf2_sha256(
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    out_len: usize,
+) -> Result<ZVec> {
+    if iterations == 0 {
+        return Err(Error::InvalidArgument);
+    }
+    let digester = Digester::sha256();
+    let mut key = ZVec::from(vec![0u8; out_len]);
+    let out = key.as_mut_slice();
+    // SAFETY: Only reads from/writes to the provided slices and the digester was non-null.
+    let ret = unsafe {
+        PKCS5_PBKDF2_HMAC(
+            password.as_ptr() as *const _,
+            password.len(),
+            salt.as_ptr(),
+            salt.len(),
+            iterations,
+            digester.0,
+            out.len(),
+            out.as_mut_ptr(),
+        )
+    };
+    check_int_result(ret, ApiName::PKCS5_PBKDF2_HMAC)?;
+    Ok(key)
+}
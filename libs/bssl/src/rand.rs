@@ -19,6 +19,9 @@ use bssl_avf_error::{ApiName, Result};
 use bssl_sys::RAND_bytes;
 
 /// Fills the given `dest` with random data.
+///
+/// In `vmbase` guests such as the service VM, BoringSSL seeds its generator with `getentropy()`
+/// from `vmbase`, so this can be used for nonces and challenges.
 pub fn rand_bytes(dest: &mut [u8]) -> Result<()> {
     // SAFETY: This function only writes to the given buffer within its bounds.
     let ret = unsafe { RAND_bytes(dest.as_mut_ptr(), dest.len()) };
@@ -14,13 +14,21 @@
 
 //! Wrappers of the SHA functions in BoringSSL sha.h.
 
-use crate::util::to_call_failed_error;
+use crate::digest::{Digester, DigesterContext};
+use crate::util::{check_int_result, to_call_failed_error};
 use bssl_avf_error::{ApiName, Result};
-use bssl_sys::SHA256;
+use bssl_sys::{EVP_DigestFinal_ex, EVP_DigestInit_ex, EVP_DigestUpdate, SHA256};
+use core::ptr;
 
 /// The length of a SHA256 digest.
 pub(crate) const SHA256_DIGEST_LENGTH: usize = bssl_sys::SHA256_DIGEST_LENGTH as usize;
 
+/// The length of a SHA384 digest.
+pub(crate) const SHA384_DIGEST_LENGTH: usize = bssl_sys::SHA384_DIGEST_LENGTH as usize;
+
+/// The length of a SHA512 digest.
+pub(crate) const SHA512_DIGEST_LENGTH: usize = bssl_sys::SHA512_DIGEST_LENGTH as usize;
+
 /// Computes the SHA256 digest of the provided `data``.
 pub fn sha256(data: &[u8]) -> Result<[u8; SHA256_DIGEST_LENGTH]> {
     let mut out = [0u8; SHA256_DIGEST_LENGTH];
@@ -33,3 +41,56 @@ pub fn sha256(data: &[u8]) -> Result<[u8; SHA256_DIGEST_LENGTH]> {
         Ok(out)
     }
 }
+
+/// Computes the SHA384 digest of the provided `data`.
+pub fn sha384(data: &[u8]) -> Result<[u8; SHA384_DIGEST_LENGTH]> {
+    digest_to_array(&Digester::sha384(), data)
+}
+
+/// Computes the SHA512 digest of the provided `data`.
+pub fn sha512(data: &[u8]) -> Result<[u8; SHA512_DIGEST_LENGTH]> {
+    digest_to_array(&Digester::sha512(), data)
+}
+
+fn digest_to_array<const N: usize>(digester: &Digester, data: &[u8]) -> Result<[u8; N]> {
+    digester.digest(data)?.try_into().map_err(|_| to_call_failed_error(ApiName::EVP_Digest))
+}
+
+/// Incremental SHA256 computation over a `DigesterContext`.
+pub struct Sha256(DigesterContext);
+
+impl Sha256 {
+    /// Creates a new `Sha256` context with no data hashed yet.
+    pub fn new() -> Result<Self> {
+        let mut ctx = DigesterContext::new()?;
+        // Use the default engine.
+        let engine = ptr::null_mut();
+        // SAFETY: This function only initializes the context, which has been allocated by
+        // BoringSSL above. The digester is a valid pointer to a static `EVP_MD`.
+        let ret = unsafe { EVP_DigestInit_ex(ctx.as_mut_ptr(), Digester::sha256().0, engine) };
+        check_int_result(ret, ApiName::EVP_DigestInit_ex)?;
+        Ok(Self(ctx))
+    }
+
+    /// Appends `data` to the message being hashed.
+    pub fn update(&mut self, data: &[u8]) -> Result<()> {
+        // SAFETY: This function only reads `data` within its bounds and updates the context,
+        // which has been initialized when this instance was created.
+        let ret =
+            unsafe { EVP_DigestUpdate(self.0.as_mut_ptr(), data.as_ptr() as *const _, data.len()) };
+        check_int_result(ret, ApiName::EVP_DigestUpdate)
+    }
+
+    /// Completes the computation and returns the SHA256 digest of all the data passed to
+    /// `update`.
+    pub fn finalize(mut self) -> Result<[u8; SHA256_DIGEST_LENGTH]> {
+        let mut out = [0u8; SHA256_DIGEST_LENGTH];
+        // The size of a SHA256 digest is already known.
+        let out_size = ptr::null_mut();
+        // SAFETY: This function writes to `out`, which has `SHA256_DIGEST_LENGTH` bytes of
+        // space for write, and reads the context initialized when this instance was created.
+        let ret = unsafe { EVP_DigestFinal_ex(self.0.as_mut_ptr(), out.as_mut_ptr(), out_size) };
+        check_int_result(ret, ApiName::EVP_DigestFinal_ex)?;
+        Ok(out)
+    }
+}
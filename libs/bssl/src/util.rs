@@ -16,6 +16,7 @@
 
 use crate::err::process_error_queue;
 use bssl_avf_error::{ApiName, Error, Result};
+use bssl_sys::CRYPTO_memcmp;
 use log::error;
 
 pub(crate) fn check_int_result(ret: i32, api_name: ApiName) -> Result<()> {
@@ -35,3 +36,16 @@ pub(crate) fn check_int_result(ret: i32, api_name: ApiName) -> Result<()> {
 pub(crate) fn to_call_failed_error(api_name: ApiName) -> Error {
     Error::CallFailed(api_name, process_error_queue())
 }
+
+/// Compares `a` and `b` in constant time and returns true if they are equal.
+///
+/// The time taken depends on the lengths of the slices but not on their contents, so this
+/// should be used instead of `==` whenever either side is secret, e.g. a MAC tag.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    // SAFETY: This function only reads `a` and `b` within their bounds, which are both
+    // `a.len()` bytes long.
+    unsafe { CRYPTO_memcmp(a.as_ptr() as *const _, b.as_ptr() as *const _, a.len()) == 0 }
+}
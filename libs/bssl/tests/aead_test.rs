@@ -12,7 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bssl_avf::{Aead, AeadContext, ApiName, CipherError, Error, ReasonCode, Result};
+use bssl_avf::{
+    aes_gcm_decrypt, aes_gcm_encrypt, chacha20_poly1305_decrypt, chacha20_poly1305_encrypt,
+    decrypt, encrypt, Aead, AeadContext, ApiName, CipherError, Error, ReasonCode, Result,
+};
 
 /// The following vectors are generated randomly with:
 /// `hexdump -vn32 -e'32/1 "0x%02x, " 1 "\n"' /dev/urandom`
@@ -30,6 +33,8 @@ const AES_256_GCM_NONCE1: [u8; 12] =
     [0x56, 0x96, 0x73, 0xe1, 0xc6, 0x3d, 0xca, 0x9a, 0x2f, 0xad, 0x3b, 0xeb];
 const AES_256_GCM_NONCE2: [u8; 12] =
     [0xa0, 0x27, 0xea, 0x3a, 0x29, 0xfa, 0x8a, 0x49, 0x35, 0x07, 0x32, 0xec];
+const CHACHA20_POLY1305_NONCE: [u8; 12] =
+    [0xdd, 0xe2, 0xf7, 0x94, 0x18, 0xf4, 0x87, 0xe2, 0x68, 0x8c, 0x67, 0x21];
 const MESSAGE: &[u8] = b"aead_aes_256_gcm test message";
 
 #[test]
@@ -134,6 +139,86 @@ fn aes_256_gcm_fails_to_decrypt_corrupted_ciphertext() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn aes_gcm_encrypt_matches_aead_context() -> Result<()> {
+    let ad = &[];
+    let ciphertext = aes_gcm_encrypt(&KEY1, &AES_256_GCM_NONCE1, ad, MESSAGE)?;
+
+    assert_eq!(aes_256_gcm_encrypt(MESSAGE)?, ciphertext);
+    let plaintext = aes_gcm_decrypt(&KEY1, &AES_256_GCM_NONCE1, ad, &ciphertext)?;
+    assert_eq!(MESSAGE, plaintext.as_slice());
+    Ok(())
+}
+
+#[test]
+fn aes_gcm_decrypt_fails_with_corrupted_tag() -> Result<()> {
+    let ad = &[];
+    let mut ciphertext = aes_gcm_encrypt(&KEY1, &AES_256_GCM_NONCE1, ad, MESSAGE)?;
+    let last = ciphertext.len() - 1;
+    ciphertext[last] = !ciphertext[last];
+
+    let err = aes_gcm_decrypt(&KEY1, &AES_256_GCM_NONCE1, ad, &ciphertext).unwrap_err();
+
+    let expected_err =
+        Error::CallFailed(ApiName::EVP_AEAD_CTX_open, ReasonCode::Cipher(CipherError::BadDecrypt));
+    assert_eq!(expected_err, err);
+    Ok(())
+}
+
+#[test]
+fn chacha20_poly1305_encrypts_and_decrypts_successfully() -> Result<()> {
+    let ad = &[1, 2, 3];
+    let ciphertext = chacha20_poly1305_encrypt(&KEY1, &CHACHA20_POLY1305_NONCE, ad, MESSAGE)?;
+
+    assert_eq!(MESSAGE.len() + Aead::chacha20_poly1305().max_overhead(), ciphertext.len());
+    let plaintext = chacha20_poly1305_decrypt(&KEY1, &CHACHA20_POLY1305_NONCE, ad, &ciphertext)?;
+    assert_eq!(MESSAGE, plaintext.as_slice());
+    Ok(())
+}
+
+#[test]
+fn chacha20_poly1305_fails_to_decrypt_with_wrong_key() -> Result<()> {
+    let ad = &[];
+    let ciphertext = chacha20_poly1305_encrypt(&KEY1, &CHACHA20_POLY1305_NONCE, ad, MESSAGE)?;
+
+    let err =
+        chacha20_poly1305_decrypt(&KEY2, &CHACHA20_POLY1305_NONCE, ad, &ciphertext).unwrap_err();
+
+    let expected_err =
+        Error::CallFailed(ApiName::EVP_AEAD_CTX_open, ReasonCode::Cipher(CipherError::BadDecrypt));
+    assert_eq!(expected_err, err);
+    Ok(())
+}
+
+#[test]
+fn encrypt_and_decrypt_use_the_given_aead() -> Result<()> {
+    let ad = &[];
+    let chacha20_poly1305 = Aead::chacha20_poly1305();
+    let ciphertext = encrypt(chacha20_poly1305, &KEY1, &CHACHA20_POLY1305_NONCE, ad, MESSAGE)?;
+
+    assert_eq!(
+        chacha20_poly1305_encrypt(&KEY1, &CHACHA20_POLY1305_NONCE, ad, MESSAGE)?,
+        ciphertext
+    );
+    let plaintext = decrypt(chacha20_poly1305, &KEY1, &CHACHA20_POLY1305_NONCE, ad, &ciphertext)?;
+    assert_eq!(MESSAGE, plaintext.as_slice());
+    Ok(())
+}
+
+#[test]
+fn decrypt_fails_with_a_different_aead() -> Result<()> {
+    let ad = &[];
+    let ciphertext = encrypt(Aead::aes_256_gcm(), &KEY1, &AES_256_GCM_NONCE1, ad, MESSAGE)?;
+
+    let err = decrypt(Aead::chacha20_poly1305(), &KEY1, &AES_256_GCM_NONCE1, ad, &ciphertext)
+        .unwrap_err();
+
+    let expected_err =
+        Error::CallFailed(ApiName::EVP_AEAD_CTX_open, ReasonCode::Cipher(CipherError::BadDecrypt));
+    assert_eq!(expected_err, err);
+    Ok(())
+}
+
 fn aes_256_gcm_encrypt(message: &[u8]) -> Result<Vec<u8>> {
     let tag_len = None;
     let aead_ctx = AeadContext::new(Aead::aes_256_gcm(), &KEY1, tag_len)?;
@@ -0,0 +1,62 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bssl_avf::{ApiName, Ed25519, Error, PKey, Result, X25519};
+
+const MESSAGE1: &[u8] = b"test message 1";
+const MESSAGE2: &[u8] = b"test message 2";
+
+#[test]
+fn x25519_agreement_results_in_same_secret() -> Result<()> {
+    let alice = X25519::generate();
+    let bob = X25519::generate();
+
+    let alice_secret = alice.agree(&bob.public_key())?;
+    let bob_secret = bob.agree(&alice.public_key())?;
+
+    assert_eq!(alice_secret.as_slice(), bob_secret.as_slice());
+    assert_ne!(alice.public_key(), bob.public_key());
+    Ok(())
+}
+
+#[test]
+fn x25519_agreement_fails_with_small_order_point() {
+    let key = X25519::generate();
+    let small_order_point = [0u8; 32];
+
+    let err = key.agree(&small_order_point).unwrap_err();
+
+    assert!(matches!(err, Error::CallFailed(ApiName::X25519, _)));
+}
+
+#[test]
+fn ed25519_signing_and_verification_succeed() -> Result<()> {
+    let key = Ed25519::generate();
+    let signature = key.sign(MESSAGE1)?;
+
+    Ed25519::verify(&key.public_key(), MESSAGE1, &signature)?;
+    let pkey = PKey::from_cose_public_key(&key.cose_public_key())?;
+    pkey.verify(&signature, MESSAGE1, None)
+}
+
+#[test]
+fn verifying_ed25519_signed_with_a_different_message_fails() -> Result<()> {
+    let key = Ed25519::generate();
+    let signature = key.sign(MESSAGE1)?;
+
+    let err = Ed25519::verify(&key.public_key(), MESSAGE2, &signature).unwrap_err();
+
+    assert!(matches!(err, Error::CallFailed(ApiName::ED25519_verify, _)));
+    Ok(())
+}
@@ -12,8 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bssl_avf::{sha256, ApiName, Digester, EcKey, EcdsaError, Error, PKey, Result};
-use coset::CborSerializable;
+use bssl_avf::{
+    ecdsa_der_to_raw, ecdsa_raw_to_der, sha256, ApiName, Digester, EcError, EcKey, EcdsaError,
+    Error, PKey, Result,
+};
+use coset::{iana, CborSerializable, CoseKeyBuilder, CoseSign1};
 use spki::{
     der::{AnyRef, Decode, Encode},
     AlgorithmIdentifier, ObjectIdentifier, SubjectPublicKeyInfoRef,
@@ -36,6 +39,44 @@ fn ec_private_key_serialization() -> Result<()> {
     let deserialized_ec_key = EcKey::from_ec_private_key(der_encoded_ec_private_key.as_slice())?;
 
     assert_eq!(ec_key.cose_public_key()?, deserialized_ec_key.cose_public_key()?);
+    assert_eq!(
+        der_encoded_ec_private_key.as_slice(),
+        deserialized_ec_key.ec_private_key()?.as_slice()
+    );
+    ec_key.verify(MESSAGE1, &deserialized_ec_key.sign(MESSAGE1)?)
+}
+
+#[test]
+fn ec_key_derived_from_the_same_seed_is_the_same() -> Result<()> {
+    let seed = [1u8; 32];
+    let ec_key1 = EcKey::derive_from_seed(&seed)?;
+    let ec_key2 = EcKey::derive_from_seed(&seed)?;
+    ec_key1.check_key()?;
+    assert_eq!(ec_key1.ec_private_key()?.as_slice(), ec_key2.ec_private_key()?.as_slice());
+
+    let ec_key3 = EcKey::derive_from_seed(&[2u8; 32])?;
+    assert_ne!(ec_key1.cose_public_key()?, ec_key3.cose_public_key()?);
+    Ok(())
+}
+
+#[test]
+fn malformed_ec_private_key_is_rejected() {
+    let err = EcKey::from_ec_private_key(b"not a private key").err();
+    let expected_err =
+        Error::CallFailed(ApiName::EC_KEY_parse_private_key, EcError::DecodeError.into());
+    assert_eq!(Some(expected_err), err);
+}
+
+#[test]
+fn p384_ec_private_key_is_rejected() -> Result<()> {
+    let mut ec_key = EcKey::new_p384()?;
+    ec_key.generate_key()?;
+    let der_encoded_ec_private_key = ec_key.ec_private_key()?;
+
+    let err = EcKey::from_ec_private_key(der_encoded_ec_private_key.as_slice()).err();
+    let expected_err =
+        Error::CallFailed(ApiName::EC_KEY_parse_private_key, EcError::GroupMismatch.into());
+    assert_eq!(Some(expected_err), err);
     Ok(())
 }
 
@@ -57,6 +98,63 @@ fn subject_public_key_info_serialization() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn public_key_spki_der_serialization() -> Result<()> {
+    let mut ec_key = EcKey::new_p256()?;
+    ec_key.generate_key()?;
+    let spki_der = ec_key.public_key_spki_der()?;
+
+    let subject_public_key_info = SubjectPublicKeyInfoRef::from_der(&spki_der).unwrap();
+    let expected_algorithm = AlgorithmIdentifier {
+        oid: X509_NIST_OID,
+        parameters: Some(AnyRef::from(&ALGO_PARAM_P256_OID)),
+    };
+    assert_eq!(expected_algorithm, subject_public_key_info.algorithm);
+    let pkey: PKey = ec_key.try_into()?;
+    assert_eq!(pkey.subject_public_key_info()?, spki_der);
+    Ok(())
+}
+
+#[test]
+fn p256_public_key_point_serialization() -> Result<()> {
+    let mut ec_key = EcKey::new_p256()?;
+    ec_key.generate_key()?;
+    let point = ec_key.public_key_point()?;
+
+    let (prefix, coordinates) = point.split_at(1);
+    let (x, y) = coordinates.split_at(32);
+    assert_eq!([0x04], prefix);
+    let expected_key =
+        CoseKeyBuilder::new_ec2_pub_key(iana::EllipticCurve::P_256, x.to_vec(), y.to_vec())
+            .algorithm(iana::Algorithm::ES256)
+            .build();
+    assert_eq!(expected_key, ec_key.cose_public_key()?);
+    Ok(())
+}
+
+#[test]
+fn p256_compressed_public_key_point_serialization() -> Result<()> {
+    let mut ec_key = EcKey::new_p256()?;
+    ec_key.generate_key()?;
+    let point = ec_key.public_key_point()?;
+    let compressed_point = ec_key.public_key_point_compressed()?;
+
+    let expected_prefix = 0x02 | (point[64] & 1);
+    assert_eq!(expected_prefix, compressed_point[0]);
+    assert_eq!(point[1..33], compressed_point[1..]);
+    Ok(())
+}
+
+#[test]
+fn p384_public_key_point_is_unimplemented() -> Result<()> {
+    let mut ec_key = EcKey::new_p384()?;
+    ec_key.generate_key()?;
+
+    assert_eq!(Some(Error::Unimplemented), ec_key.public_key_point().err());
+    assert_eq!(Some(Error::Unimplemented), ec_key.public_key_point_compressed().err());
+    Ok(())
+}
+
 #[test]
 fn p256_cose_public_key_serialization() -> Result<()> {
     let mut ec_key = EcKey::new_p256()?;
@@ -69,6 +167,43 @@ fn p384_cose_public_key_serialization() -> Result<()> {
     check_cose_public_key_serialization(&mut ec_key)
 }
 
+#[test]
+fn p521_cose_public_key_serialization() -> Result<()> {
+    let mut ec_key = EcKey::new_p521()?;
+    check_cose_public_key_serialization(&mut ec_key)
+}
+
+#[test]
+fn cose_public_key_algorithm_matches_curve() -> Result<()> {
+    for (mut ec_key, algorithm) in [
+        (EcKey::new_p256()?, iana::Algorithm::ES256),
+        (EcKey::new_p384()?, iana::Algorithm::ES384),
+        (EcKey::new_p521()?, iana::Algorithm::ES512),
+    ] {
+        ec_key.generate_key()?;
+        let cose_key = ec_key.cose_public_key()?;
+        assert_eq!(Some(coset::Algorithm::Assigned(algorithm)), cose_key.alg);
+    }
+    Ok(())
+}
+
+#[test]
+fn cose_public_key_with_kid_only_differs_by_kid() -> Result<()> {
+    let mut ec_key = EcKey::new_p256()?;
+    ec_key.generate_key()?;
+    let kid = b"key-1";
+    let cose_key = ec_key.cose_public_key()?;
+    let cose_key_with_kid = ec_key.cose_public_key_with_kid(kid)?;
+
+    assert!(cose_key.key_id.is_empty());
+    assert_eq!(kid.to_vec(), cose_key_with_kid.key_id);
+    assert_eq!(cose_key, coset::CoseKey { key_id: Vec::new(), ..cose_key_with_kid.clone() });
+    let deserialized_ec_key =
+        EcKey::from_cose_public_key_slice(&cose_key_with_kid.to_vec().unwrap())?;
+    assert_eq!(cose_key, deserialized_ec_key.cose_public_key()?);
+    Ok(())
+}
+
 fn check_cose_public_key_serialization(ec_key: &mut EcKey) -> Result<()> {
     ec_key.generate_key()?;
     let cose_key = ec_key.cose_public_key()?;
@@ -79,6 +214,83 @@ fn check_cose_public_key_serialization(ec_key: &mut EcKey) -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn cose_public_key_coordinates_are_zero_padded() -> Result<()> {
+    // About 1 in 128 P-256 public keys has a coordinate with a leading zero byte, so some of
+    // these keys are bound to have one.
+    for i in 0u32..1024 {
+        let mut seed = [0u8; 32];
+        seed[..4].copy_from_slice(&i.to_be_bytes());
+        let cose_key = EcKey::derive_from_seed(&seed)?.cose_public_key()?;
+
+        let coordinates: Vec<_> =
+            cose_key.params.iter().filter_map(|(_, value)| value.as_bytes()).collect();
+        assert_eq!(2, coordinates.len());
+        assert!(coordinates.iter().all(|coordinate| coordinate.len() == 32));
+    }
+    Ok(())
+}
+
+#[test]
+fn cose_public_key_with_malformed_coordinates_is_rejected() {
+    let cose_key =
+        CoseKeyBuilder::new_ec2_pub_key(iana::EllipticCurve::P_256, vec![1; 31], vec![1; 32])
+            .build();
+
+    let err = EcKey::from_cose_public_key(&cose_key).err();
+    assert_eq!(Some(Error::CoseKeyDecodingFailed), err);
+}
+
+#[test]
+fn cose_public_key_with_oversized_coordinates_is_rejected() {
+    let cose_key =
+        CoseKeyBuilder::new_ec2_pub_key(iana::EllipticCurve::P_256, vec![1; 33], vec![1; 33])
+            .build();
+
+    let err = EcKey::from_cose_public_key(&cose_key).err();
+    assert_eq!(Some(Error::CoseKeyDecodingFailed), err);
+}
+
+#[test]
+fn cose_public_key_with_point_not_on_curve_is_rejected() {
+    let cose_key =
+        CoseKeyBuilder::new_ec2_pub_key(iana::EllipticCurve::P_256, vec![1; 32], vec![1; 32])
+            .build();
+
+    let err = EcKey::from_cose_public_key(&cose_key).err();
+    let expected_err = Error::CallFailed(
+        ApiName::EC_KEY_set_public_key_affine_coordinates,
+        EcError::PointIsNotOnCurve.into(),
+    );
+    assert_eq!(Some(expected_err), err);
+}
+
+#[test]
+fn ecdh_shared_secrets_match() -> Result<()> {
+    let mut ec_key1 = EcKey::new_p256()?;
+    ec_key1.generate_key()?;
+    let mut ec_key2 = EcKey::new_p256()?;
+    ec_key2.generate_key()?;
+
+    let secret1 = ec_key1.ecdh(&ec_key2.cose_public_key()?)?;
+    let secret2 = ec_key2.ecdh(&ec_key1.cose_public_key()?)?;
+    assert_eq!(secret1.as_slice(), secret2.as_slice());
+    assert_eq!(32, secret1.as_slice().len());
+    Ok(())
+}
+
+#[test]
+fn ecdh_with_a_key_on_a_different_curve_fails() -> Result<()> {
+    let mut ec_key1 = EcKey::new_p256()?;
+    ec_key1.generate_key()?;
+    let mut ec_key2 = EcKey::new_p384()?;
+    ec_key2.generate_key()?;
+
+    let err = ec_key1.ecdh(&ec_key2.cose_public_key()?).err();
+    assert_eq!(Some(Error::CoseKeyDecodingFailed), err);
+    Ok(())
+}
+
 #[test]
 fn ecdsa_p256_signing_and_verification_succeed() -> Result<()> {
     let mut ec_key = EcKey::new_p256()?;
@@ -95,6 +307,29 @@ fn ecdsa_p256_signing_and_verification_succeed() -> Result<()> {
     pkey.verify(&signature, MESSAGE1, Some(digester))
 }
 
+#[test]
+fn ecdsa_p256_signing_message_succeeds() -> Result<()> {
+    let mut ec_key = EcKey::new_p256()?;
+    ec_key.generate_key()?;
+
+    let signature = ec_key.sign(MESSAGE1)?;
+    ec_key.ecdsa_verify_der(&signature, &sha256(MESSAGE1)?)
+}
+
+#[test]
+fn ecdsa_p256_verification_with_cose_public_key_succeeds() -> Result<()> {
+    let mut ec_key = EcKey::new_p256()?;
+    ec_key.generate_key()?;
+    let signature = ec_key.sign(MESSAGE1)?;
+
+    let public_key = EcKey::from_cose_public_key(&ec_key.cose_public_key()?)?;
+    public_key.verify(MESSAGE1, &signature)?;
+    let err = public_key.verify(MESSAGE2, &signature).unwrap_err();
+    let expected_err = Error::CallFailed(ApiName::ECDSA_verify, EcdsaError::BadSignature.into());
+    assert_eq!(expected_err, err);
+    Ok(())
+}
+
 #[test]
 fn ecdsa_p384_signing_and_verification_succeed() -> Result<()> {
     let mut ec_key = EcKey::new_p384()?;
@@ -108,6 +343,19 @@ fn ecdsa_p384_signing_and_verification_succeed() -> Result<()> {
     pkey.verify(&signature, MESSAGE1, Some(digester))
 }
 
+#[test]
+fn ecdsa_p521_signing_and_verification_succeed() -> Result<()> {
+    let mut ec_key = EcKey::new_p521()?;
+    ec_key.generate_key()?;
+    let digester = Digester::sha512();
+    let digest = digester.digest(MESSAGE1)?;
+
+    let signature = ec_key.ecdsa_sign_der(&digest)?;
+    ec_key.ecdsa_verify_der(&signature, &digest)?;
+    let pkey = PKey::from_cose_public_key(&ec_key.cose_public_key()?)?;
+    pkey.verify(&signature, MESSAGE1, Some(digester))
+}
+
 #[test]
 fn verifying_ecdsa_p256_signed_with_a_different_key_fails() -> Result<()> {
     let mut ec_key1 = EcKey::new_p256()?;
@@ -169,6 +417,56 @@ fn verifying_ecdsa_cose_signed_with_a_different_message_fails() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn cose_sign1_signing_and_verification_succeed() -> Result<()> {
+    const AAD: &[u8] = b"aad";
+    let mut ec_key = EcKey::new_p256()?;
+    ec_key.generate_key()?;
+
+    let cose_sign1 = ec_key.sign_cose_sign1(MESSAGE1, AAD)?;
+    let cose_sign1 = CoseSign1::from_slice(&cose_sign1).unwrap();
+    assert_eq!(Some(MESSAGE1.to_vec()), cose_sign1.payload);
+    assert_eq!(
+        Some(coset::Algorithm::Assigned(iana::Algorithm::ES256)),
+        cose_sign1.protected.header.alg
+    );
+    assert_eq!(64, cose_sign1.signature.len());
+    cose_sign1.verify_signature(AAD, |signature, message| {
+        ec_key.ecdsa_verify_cose(signature, &sha256(message)?)
+    })?;
+    let err = cose_sign1
+        .verify_signature(b"other aad", |signature, message| {
+            ec_key.ecdsa_verify_cose(signature, &sha256(message)?)
+        })
+        .unwrap_err();
+    let expected_err = Error::CallFailed(ApiName::ECDSA_verify, EcdsaError::BadSignature.into());
+    assert_eq!(expected_err, err);
+    Ok(())
+}
+
+#[test]
+fn ecdsa_signature_conversion_keeps_zero_padding() -> Result<()> {
+    let mut raw = [0u8; 64];
+    raw[31] = 1;
+    raw[63] = 2;
+
+    let der = ecdsa_raw_to_der(&raw)?;
+    assert_eq!([0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x02], der.as_slice());
+    assert_eq!(raw.as_slice(), ecdsa_der_to_raw(&der, 32)?);
+    Ok(())
+}
+
+#[test]
+fn ecdsa_der_signature_converts_to_verifiable_raw_signature() -> Result<()> {
+    let digest = sha256(MESSAGE1)?;
+    let mut ec_key = EcKey::new_p256()?;
+    ec_key.generate_key()?;
+
+    let raw = ecdsa_der_to_raw(&ec_key.ecdsa_sign_der(&digest)?, 32)?;
+    assert_eq!(64, raw.len());
+    ec_key.ecdsa_verify_cose(&raw, &digest)
+}
+
 #[test]
 fn verifying_ecdsa_cose_signed_as_der_fails() -> Result<()> {
     let digest = sha256(MESSAGE1)?;
@@ -181,3 +479,32 @@ fn verifying_ecdsa_cose_signed_as_der_fails() -> Result<()> {
     assert_eq!(expected_err, err);
     Ok(())
 }
+
+#[test]
+fn cloned_ec_key_outlives_original() -> Result<()> {
+    let mut ec_key = EcKey::new_p256()?;
+    ec_key.generate_key()?;
+    let cloned_ec_key = ec_key.try_clone()?;
+    let signature = ec_key.sign(MESSAGE1)?;
+    let private_key = ec_key.ec_private_key()?;
+    drop(ec_key);
+
+    assert_eq!(private_key.as_slice(), cloned_ec_key.ec_private_key()?.as_slice());
+    cloned_ec_key.verify(MESSAGE1, &signature)?;
+    cloned_ec_key.verify(MESSAGE1, &cloned_ec_key.sign(MESSAGE1)?)
+}
+
+#[test]
+fn public_key_eq_matches_only_the_same_key() -> Result<()> {
+    let mut ec_key = EcKey::new_p256()?;
+    ec_key.generate_key()?;
+    let mut other_ec_key = EcKey::new_p256()?;
+    other_ec_key.generate_key()?;
+    let mut p384_ec_key = EcKey::new_p384()?;
+    p384_ec_key.generate_key()?;
+
+    assert!(ec_key.public_key_eq(&ec_key.cose_public_key()?)?);
+    assert!(!ec_key.public_key_eq(&other_ec_key.cose_public_key()?)?);
+    assert!(!ec_key.public_key_eq(&p384_ec_key.cose_public_key()?)?);
+    Ok(())
+}
@@ -16,7 +16,7 @@
 //!
 //! [RFC 5869]: https://datatracker.ietf.org/doc/html/rfc5869
 
-use bssl_avf::{hkdf, Digester, Result};
+use bssl_avf::{hkdf, hkdf_sha256, Digester, Result};
 
 #[test]
 fn rfc5869_test_case_1() -> Result<()> {
@@ -34,6 +34,7 @@ fn rfc5869_test_case_1() -> Result<()> {
         0xc5, 0xbf, 0x34, 0x00, 0x72, 0x08, 0xd5, 0xb8, 0x87, 0x18, 0x58, 0x65,
     ];
     assert_eq!(OKM, hkdf::<L>(&IKM, &SALT, &INFO, Digester::sha256())?.as_slice());
+    assert_eq!(OKM, hkdf_sha256(&IKM, &SALT, &INFO, L)?.as_slice());
     Ok(())
 }
 
@@ -16,7 +16,7 @@
 //!
 //! [RFC 4231]: https://datatracker.ietf.org/doc/html/rfc4231
 
-use bssl_avf::{hmac_sha256, Result};
+use bssl_avf::{hmac_sha256, HmacCtx, Result};
 
 #[test]
 fn rfc4231_test_case_1() -> Result<()> {
@@ -111,5 +111,11 @@ fn rfc4231_test_case_7() -> Result<()> {
         0x35, 0xe2,
     ];
     assert_eq!(HMAC_SHA256, hmac_sha256(KEY, DATA.as_bytes())?);
+
+    let mut hmac_ctx = HmacCtx::sha256(KEY)?;
+    for chunk in DATA.as_bytes().chunks(17) {
+        hmac_ctx.update(chunk)?;
+    }
+    assert_eq!(HMAC_SHA256.as_slice(), hmac_ctx.finalize()?);
     Ok(())
 }
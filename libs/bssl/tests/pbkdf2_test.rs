@@ -0,0 +1,52 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests PBKDF2-HMAC-SHA256 with the test vectors in [RFC 7914] Section 11.
+//!
+//! [RFC 7914]: https://datatracker.ietf.org/doc/html/rfc7914#section-11
+
+use bssl_avf::{pbkdf2_sha256, Error, Result};
+
+#[test]
+fn rfc7914_test_case_1() -> Result<()> {
+    const DK: [u8; 64] = [
+        0x55, 0xac, 0x04, 0x6e, 0x56, 0xe3, 0x08, 0x9f, 0xec, 0x16, 0x91, 0xc2, 0x25, 0x44, 0xb6,
+        0x05, 0xf9, 0x41, 0x85, 0x21, 0x6d, 0xde, 0x04, 0x65, 0xe6, 0x8b, 0x9d, 0x57, 0xc2, 0x0d,
+        0xac, 0xbc, 0x49, 0xca, 0x9c, 0xcc, 0xf1, 0x79, 0xb6, 0x45, 0x99, 0x16, 0x64, 0xb3, 0x9d,
+        0x77, 0xef, 0x31, 0x7c, 0x71, 0xb8, 0x45, 0xb1, 0xe3, 0x0b, 0xd5, 0x09, 0x11, 0x20, 0x41,
+        0xd3, 0xa1, 0x97, 0x83,
+    ];
+    assert_eq!(DK, pbkdf2_sha256(b"passwd", b"salt", 1, DK.len())?.as_slice());
+    Ok(())
+}
+
+#[test]
+fn rfc7914_test_case_2() -> Result<()> {
+    const DK: [u8; 64] = [
+        0x4d, 0xdc, 0xd8, 0xf6, 0x0b, 0x98, 0xbe, 0x21, 0x83, 0x0c, 0xee, 0x5e, 0xf2, 0x27, 0x01,
+        0xf9, 0x64, 0x1a, 0x44, 0x18, 0xd0, 0x4c, 0x04, 0x14, 0xae, 0xff, 0x08, 0x87, 0x6b, 0x34,
+        0xab, 0x56, 0xa1, 0xd4, 0x25, 0xa1, 0x22, 0x58, 0x33, 0x54, 0x9a, 0xdb, 0x84, 0x1b, 0x51,
+        0xc9, 0xb3, 0x17, 0x6a, 0x27, 0x2b, 0xde, 0xbb, 0xa1, 0xd0, 0x78, 0x47, 0x8f, 0x62, 0xb3,
+        0x97, 0xf3, 0x3c, 0x8d,
+    ];
+    assert_eq!(DK, pbkdf2_sha256(b"Password", b"NaCl", 80000, DK.len())?.as_slice());
+    Ok(())
+}
+
+#[test]
+fn pbkdf2_with_zero_iterations_fails() {
+    let err = pbkdf2_sha256(b"passwd", b"salt", 0, 32).unwrap_err();
+
+    assert_eq!(err, Error::InvalidArgument);
+}
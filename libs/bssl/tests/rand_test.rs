@@ -0,0 +1,32 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bssl_avf::{rand_bytes, Result};
+
+#[test]
+fn rand_bytes_fills_buffer() -> Result<()> {
+    let mut nonce1 = [0u8; 32];
+    let mut nonce2 = [0u8; 32];
+    rand_bytes(&mut nonce1)?;
+    rand_bytes(&mut nonce2)?;
+
+    assert_ne!([0u8; 32], nonce1);
+    assert_ne!(nonce1, nonce2);
+    Ok(())
+}
+
+#[test]
+fn rand_bytes_accepts_empty_buffer() -> Result<()> {
+    rand_bytes(&mut [])
+}
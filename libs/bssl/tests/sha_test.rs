@@ -0,0 +1,73 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests SHA-2 with the "abc" test vectors of [FIPS 180-2] Appendices B, C and D.
+//!
+//! [FIPS 180-2]: https://csrc.nist.gov/publications/fips/fips180-2/fips180-2.pdf
+
+use bssl_avf::{sha256, sha384, sha512, Result, Sha256};
+
+const MESSAGE: &[u8] = b"abc";
+
+#[test]
+fn sha256_abc() -> Result<()> {
+    const SHA256: [u8; 32] = [
+        0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae, 0x22,
+        0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00,
+        0x15, 0xad,
+    ];
+    assert_eq!(SHA256, sha256(MESSAGE)?);
+    Ok(())
+}
+
+#[test]
+fn sha384_abc() -> Result<()> {
+    const SHA384: [u8; 48] = [
+        0xcb, 0x00, 0x75, 0x3f, 0x45, 0xa3, 0x5e, 0x8b, 0xb5, 0xa0, 0x3d, 0x69, 0x9a, 0xc6, 0x50,
+        0x07, 0x27, 0x2c, 0x32, 0xab, 0x0e, 0xde, 0xd1, 0x63, 0x1a, 0x8b, 0x60, 0x5a, 0x43, 0xff,
+        0x5b, 0xed, 0x80, 0x86, 0x07, 0x2b, 0xa1, 0xe7, 0xcc, 0x23, 0x58, 0xba, 0xec, 0xa1, 0x34,
+        0xc8, 0x25, 0xa7,
+    ];
+    assert_eq!(SHA384, sha384(MESSAGE)?);
+    Ok(())
+}
+
+#[test]
+fn sha512_abc() -> Result<()> {
+    const SHA512: [u8; 64] = [
+        0xdd, 0xaf, 0x35, 0xa1, 0x93, 0x61, 0x7a, 0xba, 0xcc, 0x41, 0x73, 0x49, 0xae, 0x20, 0x41,
+        0x31, 0x12, 0xe6, 0xfa, 0x4e, 0x89, 0xa9, 0x7e, 0xa2, 0x0a, 0x9e, 0xee, 0xe6, 0x4b, 0x55,
+        0xd3, 0x9a, 0x21, 0x92, 0x99, 0x2a, 0x27, 0x4f, 0xc1, 0xa8, 0x36, 0xba, 0x3c, 0x23, 0xa3,
+        0xfe, 0xeb, 0xbd, 0x45, 0x4d, 0x44, 0x23, 0x64, 0x3c, 0xe8, 0x0e, 0x2a, 0x9a, 0xc9, 0x4f,
+        0xa5, 0x4c, 0xa4, 0x9f,
+    ];
+    assert_eq!(SHA512, sha512(MESSAGE)?);
+    Ok(())
+}
+
+#[test]
+fn streaming_sha256_matches_one_shot() -> Result<()> {
+    let mut hasher = Sha256::new()?;
+    for chunk in MESSAGE.chunks(1) {
+        hasher.update(chunk)?;
+    }
+    assert_eq!(sha256(MESSAGE)?, hasher.finalize()?);
+    Ok(())
+}
+
+#[test]
+fn streaming_sha256_of_nothing_is_empty_digest() -> Result<()> {
+    assert_eq!(sha256(&[])?, Sha256::new()?.finalize()?);
+    Ok(())
+}
@@ -15,6 +15,11 @@
 //! API tests of the crate `bssl_avf`.
 
 mod aead_test;
+mod curve25519_test;
 mod eckey_test;
 mod hkdf_test;
 mod hmac_test;
+mod pbkdf2_test;
+mod rand_test;
+mod sha_test;
+mod util_test;
@@ -162,6 +162,7 @@ impl DiskImage {
             image: maybe_open_parcel_file(&self.image, self.writable)?,
             writable: self.writable,
             partitions,
+            ..Default::default()
         })
     }
 }
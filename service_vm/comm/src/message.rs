@@ -164,6 +164,10 @@ pub enum RequestProcessingError {
 
     /// The vendor partition loaded by the client VM is invalid.
     InvalidVendorPartition,
+
+    /// A key failed validation, e.g. its public key is not on the curve or its private key is out
+    /// of range.
+    InvalidKey,
 }
 
 impl fmt::Display for RequestProcessingError {
@@ -198,13 +202,20 @@ impl fmt::Display for RequestProcessingError {
             Self::InvalidVendorPartition => {
                 write!(f, "The vendor partition loaded by the client VM is invalid")
             }
+            Self::InvalidKey => write!(f, "The key failed validation"),
         }
     }
 }
 
 impl From<bssl_avf_error::Error> for RequestProcessingError {
     fn from(e: bssl_avf_error::Error) -> Self {
-        Self::BoringSslError(e)
+        match e {
+            bssl_avf_error::Error::CallFailed(bssl_avf_error::ApiName::EC_KEY_check_key, _) => {
+                error!("Key validation failed: {e}");
+                Self::InvalidKey
+            }
+            e => Self::BoringSslError(e),
+        }
     }
 }
 
@@ -17,9 +17,10 @@
 use crate::{get_calling_pid, get_calling_uid, get_this_pid};
 use crate::atom::{write_vm_booted_stats, write_vm_creation_stats};
 use crate::composite::make_composite_image;
-use crate::crosvm::{CrosvmConfig, DiskFile, DisplayConfig, GpuConfig, InputDeviceOption, PayloadState, VmContext, VmInstance, VmState};
+use crate::crosvm::{BOOT_HANGUP_TIMEOUT, CacheMode, ConsoleTarget, CpuTopology as VcpuTopology, CrosvmConfig, DiskFile, DiskFormat, DisplayConfig, GpuConfig, InputDeviceOption, IoPriorityClass, PayloadState, RestartPolicy, SandboxConfig, SharedDir, VmContext, VmInstance, VmState, set_stream_timeouts};
 use crate::debug_config::DebugConfig;
 use crate::dt_overlay::{create_device_tree_overlay, VM_DT_OVERLAY_MAX_SIZE, VM_DT_OVERLAY_PATH};
+use crate::kernel_params::KernelParams;
 use crate::payload::{add_microdroid_payload_images, add_microdroid_system_images, add_microdroid_vendor_image};
 use crate::selinux::{getfilecon, SeContext};
 use android_os_permissions_aidl::aidl::android::os::IPermissionController;
@@ -30,8 +31,12 @@ use android_system_virtualizationcommon::aidl::android::system::virtualizationco
 };
 use android_system_virtualizationservice::aidl::android::system::virtualizationservice::{
     AssignableDevice::AssignableDevice,
+    CacheMode::CacheMode as AidlCacheMode,
+    ConsoleTarget::{ConsoleTarget as ConsoleTargetParcelable, Type::Type as ConsoleTargetType},
     CpuTopology::CpuTopology,
+    DiskFormat::DiskFormat as AidlDiskFormat,
     DiskImage::DiskImage,
+    HostPriority::{HostPriority, IoPriorityClass::IoPriorityClass as AidlIoPriorityClass},
     InputDevice::InputDevice,
     IVirtualMachine::{BnVirtualMachine, IVirtualMachine},
     IVirtualMachineCallback::IVirtualMachineCallback,
@@ -39,6 +44,8 @@ use android_system_virtualizationservice::aidl::android::system::virtualizations
     MemoryTrimLevel::MemoryTrimLevel,
     Partition::Partition,
     PartitionType::PartitionType,
+    RestartPolicy::RestartPolicy as AidlRestartPolicy,
+    SharedDirectory::SharedDirectory,
     VirtualMachineAppConfig::{DebugLevel::DebugLevel, Payload::Payload, VirtualMachineAppConfig},
     VirtualMachineConfig::VirtualMachineConfig,
     VirtualMachineDebugInfo::VirtualMachineDebugInfo,
@@ -87,6 +94,7 @@ use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
 use std::os::unix::raw::pid_t;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
 use vbmeta::VbMetaImage;
 use vmconfig::{VmConfig, get_debug_level};
 use vsock::VsockStream;
@@ -119,6 +127,10 @@ const PARTITION_GRANULARITY_BYTES: u64 = 4096;
 
 const VM_REFERENCE_DT_ON_HOST_PATH: &str = "/proc/device-tree/avf/reference";
 
+/// How long `connectVsock` waits for the payload to accept the connection, so that a hung guest
+/// can't block the binder thread forever.
+const VSOCK_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
 lazy_static! {
     pub static ref GLOBAL_SERVICE: Strong<dyn IVirtualizationServiceInternal> =
         wait_for_interface(BINDER_SERVICE_IDENTIFIER)
@@ -169,6 +181,9 @@ fn get_current_sdk() -> Result<u32> {
     current_sdk.parse().context("Malformed SDK version")
 }
 
+/// Removes the files which a VM left in its temporary directory `path`, but not the directory
+/// itself. Snapshots are taken into subdirectories, but [`VmInstance::snapshot`] removes them once
+/// they have been copied to the client, so only files are left.
 pub fn remove_temporary_files(path: &PathBuf) -> Result<()> {
     for dir_entry in read_dir(path)? {
         remove_file(dir_entry?.path())?;
@@ -190,10 +205,20 @@ impl Interface for VirtualizationService {
         writeln!(writer, "Running {0} VMs:", vms.len()).or(Err(StatusCode::UNKNOWN_ERROR))?;
         for vm in vms {
             writeln!(writer, "VM CID: {}", vm.cid).or(Err(StatusCode::UNKNOWN_ERROR))?;
-            writeln!(writer, "\tState: {:?}", vm.vm_state.lock().unwrap())
-                .or(Err(StatusCode::UNKNOWN_ERROR))?;
+            writeln!(writer, "\tState: {:?}", vm.state()).or(Err(StatusCode::UNKNOWN_ERROR))?;
             writeln!(writer, "\tPayload state {:?}", vm.payload_state())
                 .or(Err(StatusCode::UNKNOWN_ERROR))?;
+            writeln!(writer, "\tUptime: {:?}", vm.uptime()).or(Err(StatusCode::UNKNOWN_ERROR))?;
+            writeln!(writer, "\tRestarts: {}", vm.restart_count())
+                .or(Err(StatusCode::UNKNOWN_ERROR))?;
+            if let Ok(memory_stats) = vm.memory_stats() {
+                writeln!(writer, "\tMemory: {:?}", memory_stats)
+                    .or(Err(StatusCode::UNKNOWN_ERROR))?;
+            }
+            if let Some(exit_status) = vm.exit_status() {
+                writeln!(writer, "\tExit status: {}", exit_status)
+                    .or(Err(StatusCode::UNKNOWN_ERROR))?;
+            }
             writeln!(writer, "\tProtected: {}", vm.protected).or(Err(StatusCode::UNKNOWN_ERROR))?;
             writeln!(writer, "\ttemporary_directory: {}", vm.temporary_directory.to_string_lossy())
                 .or(Err(StatusCode::UNKNOWN_ERROR))?;
@@ -410,9 +435,24 @@ impl VirtualizationService {
             None
         };
 
+        let console_target = match config {
+            VirtualMachineConfig::RawConfig(config) => {
+                config.consoleTarget.as_ref().map(to_console_target).transpose()?
+            }
+            VirtualMachineConfig::AppConfig(_) => None,
+        };
+        if console_target.is_some() && (console_out_fd.is_some() || console_in_fd.is_some()) {
+            return Err(anyhow!("Can't have both a console target and console file descriptors"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
+
         let state = &mut *self.state.lock().unwrap();
-        let console_out_fd =
-            clone_or_prepare_logger_fd(&debug_config, console_out_fd, format!("Console({})", cid))?;
+        // A console target replaces the console output which is prepared for debuggable VMs.
+        let console_out_fd = if console_target.is_some() {
+            None
+        } else {
+            clone_or_prepare_logger_fd(&debug_config, console_out_fd, format!("Console({})", cid))?
+        };
         let console_in_fd = console_in_fd.map(clone_file).transpose()?;
         let log_fd = clone_or_prepare_logger_fd(&debug_config, log_fd, format!("Log({})", cid))?;
 
@@ -500,6 +540,22 @@ impl VirtualizationService {
                     .or_service_specific_exception(-1);
             }
         };
+        let cpu_topology = config
+            .vcpuTopology
+            .as_ref()
+            .map(VcpuTopology::new)
+            .transpose()
+            .context("Invalid vCPU topology")
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?;
+        let cpus = if let Some(cpu_topology) = cpu_topology {
+            if config.cpuTopology != CpuTopology::ONE_CPU {
+                return Err(anyhow!("Can't set both a CPU topology and a vCPU topology"))
+                    .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+            }
+            NonZeroU32::new(cpu_topology.num_cpus())
+        } else {
+            cpus
+        };
 
         let (vfio_devices, dtbo) = if !config.devices.is_empty() {
             let mut set = HashSet::new();
@@ -575,9 +631,30 @@ impl VirtualizationService {
         } else {
             None
         };
+        let tap_mac_address = config
+            .tapMacAddress
+            .as_deref()
+            .map(<[u8; 6]>::try_from)
+            .transpose()
+            .context("MAC address must be 6 bytes")
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?;
+        if tap_mac_address.is_some() && tap.is_none() {
+            return Err(anyhow!("MAC address given for a VM without network"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
         let virtio_snd_backend =
             if cfg!(paravirtualized_devices) { Some(String::from("aaudio")) } else { None };
 
+        let (scheduler_priority, io_priority_class) = match &config.hostPriority {
+            Some(priority) => (Some(priority.niceness), to_io_priority_class(priority)?),
+            None => (None, None),
+        };
+        let shared_dirs =
+            config.sharedDirectories.iter().map(to_shared_dir).collect::<binder::Result<_>>()?;
+        let pmem_files = config.pmemFiles.iter().map(clone_file).collect::<binder::Result<_>>()?;
+        let device_tree_overlays =
+            config.deviceTreeOverlays.iter().map(clone_file).collect::<binder::Result<_>>()?;
+
         // Actually start the VM.
         let crosvm_config = CrosvmConfig {
             cid,
@@ -585,31 +662,51 @@ impl VirtualizationService {
             bootloader: maybe_clone_file(&config.bootloader)?,
             kernel,
             initrd,
+            bootconfig: maybe_clone_file(&config.bootconfig)?,
+            restore_from_dir: maybe_clone_file(&config.restoreFrom)?,
             disks,
-            params: config.params.to_owned(),
+            shared_dirs,
+            pmem_files,
+            params: config.params.as_deref().map(KernelParams::from).unwrap_or_default(),
             protected: *is_protected,
             debug_config,
             memory_mib: config.memoryMib.try_into().ok().and_then(NonZeroU32::new),
+            min_memory_mib: config.minMemoryMib.try_into().ok().and_then(NonZeroU32::new),
+            swiotlb_mib: None,
+            balloon: false,
             cpus,
+            cpu_topology,
+            cpu_affinity: config.cpuAffinity.clone(),
             host_cpu_topology,
             console_out_fd,
+            console_out_sinks: Default::default(),
+            console_target,
             console_in_fd,
+            watch_payload_log: is_app_config && log_fd.is_some(),
             log_fd,
             ramdump,
             indirect_files,
             platform_version: parse_platform_version_req(&config.platformVersion)?,
-            detect_hangup: is_app_config,
+            restart_policy: to_restart_policy(config)?,
+            sandbox: config.seccompPolicyDir.as_ref().map_or(SandboxConfig::Disabled, |dir| {
+                SandboxConfig::Enabled { policy_dir: dir.into() }
+            }),
+            boot_timeout: is_app_config.then(|| *BOOT_HANGUP_TIMEOUT),
             gdb_port,
             vfio_devices,
             dtbo,
             device_tree_overlay,
+            device_tree_overlays,
             display_config,
             input_device_options,
             hugepages: config.hugePages,
             tap,
+            tap_mac_address,
             virtio_snd_backend,
             console_input_device: config.consoleInputDevice.clone(),
             boost_uclamp: config.boostUclamp,
+            scheduler_priority,
+            io_priority_class,
             gpu_config,
         };
         let instance = Arc::new(
@@ -816,6 +913,14 @@ fn assemble_disk_image(
             return Err(anyhow!("DiskImage contains both image and partitions"))
                 .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
         }
+        if disk.format != AidlDiskFormat::RAW {
+            return Err(anyhow!("Composite disk images must be raw"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
+        if disk.cache == AidlCacheMode::WRITES_THROUGH {
+            return Err(anyhow!("Composite disk images can't be written through"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
+        }
 
         let composite_image_filenames =
             make_composite_image_filenames(temporary_directory, next_temporary_image_id);
@@ -843,7 +948,32 @@ fn assemble_disk_image(
             .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT);
     };
 
-    Ok(DiskFile { image, writable: disk.writable })
+    Ok(DiskFile {
+        image,
+        writable: disk.writable,
+        format: to_disk_format(disk.format)?,
+        cache: to_cache_mode(disk.cache)?,
+        block_async: disk.blockAsync,
+    })
+}
+
+fn to_disk_format(format: AidlDiskFormat) -> binder::Result<DiskFormat> {
+    match format {
+        AidlDiskFormat::RAW => Ok(DiskFormat::Raw),
+        AidlDiskFormat::QCOW2 => Ok(DiskFormat::Qcow2),
+        format => Err(anyhow!("Unknown disk format {format:?}"))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT),
+    }
+}
+
+fn to_cache_mode(cache: AidlCacheMode) -> binder::Result<CacheMode> {
+    match cache {
+        AidlCacheMode::WRITES_BACK => Ok(CacheMode::WritesBack),
+        AidlCacheMode::WRITES_THROUGH => Ok(CacheMode::WritesThrough),
+        AidlCacheMode::NONE => Ok(CacheMode::None),
+        cache => Err(anyhow!("Unknown cache mode {cache:?}"))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT),
+    }
 }
 
 fn append_kernel_param(param: &str, vm_config: &mut VirtualMachineRawConfig) {
@@ -1198,7 +1328,15 @@ impl IVirtualMachine for VirtualMachine {
         // others.
         //
         // TODO: Should this give an error if the VM is already dead?
-        self.instance.callbacks.add(callback.clone());
+        self.instance.add_callback(callback.clone());
+        Ok(())
+    }
+
+    fn unregisterCallback(
+        &self,
+        callback: &Strong<dyn IVirtualMachineCallback>,
+    ) -> binder::Result<()> {
+        self.instance.remove_callback(callback);
         Ok(())
     }
 
@@ -1218,6 +1356,43 @@ impl IVirtualMachine for VirtualMachine {
             .or_service_specific_exception(-1)
     }
 
+    fn shutdown(&self, timeout_millis: i64) -> binder::Result<()> {
+        let timeout = u64::try_from(timeout_millis)
+            .map(Duration::from_millis)
+            .map_err(|_| anyhow!("Negative shutdown timeout {timeout_millis}ms"))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?;
+        self.instance
+            .stop(timeout)
+            .with_context(|| format!("Error shutting down VM with CID {}", self.instance.cid))
+            .with_log()
+            .or_service_specific_exception(-1)
+    }
+
+    fn pause(&self) -> binder::Result<()> {
+        self.instance
+            .pause()
+            .with_context(|| format!("Error pausing VM with CID {}", self.instance.cid))
+            .with_log()
+            .or_service_specific_exception(-1)
+    }
+
+    fn resume(&self) -> binder::Result<()> {
+        self.instance
+            .resume()
+            .with_context(|| format!("Error resuming VM with CID {}", self.instance.cid))
+            .with_log()
+            .or_service_specific_exception(-1)
+    }
+
+    fn snapshot(&self, directory: &ParcelFileDescriptor, resume: bool) -> binder::Result<()> {
+        let directory = clone_file(directory)?;
+        self.instance
+            .snapshot(&directory, resume)
+            .with_context(|| format!("Error taking snapshot of VM with CID {}", self.instance.cid))
+            .with_log()
+            .or_service_specific_exception(-1)
+    }
+
     fn onTrimMemory(&self, level: MemoryTrimLevel) -> binder::Result<()> {
         self.instance
             .trim_memory(level)
@@ -1235,9 +1410,13 @@ impl IVirtualMachine for VirtualMachine {
             return Err(anyhow!("Can't connect to privileged port {port}"))
                 .or_service_specific_exception(-1);
         }
-        let stream = VsockStream::connect_with_cid_port(self.instance.cid, port)
+        let stream = self
+            .instance
+            .connect_payload_stream(port, VSOCK_CONNECT_TIMEOUT)
             .context("Failed to connect")
             .or_service_specific_exception(-1)?;
+        // The client decides for itself how long to wait on the stream.
+        set_stream_timeouts(&stream, None).or_service_specific_exception(-1)?;
         Ok(vsock_stream_to_pfd(stream))
     }
 }
@@ -1259,8 +1438,7 @@ pub struct VirtualMachineCallbacks(Mutex<Vec<Strong<dyn IVirtualMachineCallback>
 impl VirtualMachineCallbacks {
     /// Call all registered callbacks to notify that the payload has started.
     pub fn notify_payload_started(&self, cid: Cid) {
-        let callbacks = &*self.0.lock().unwrap();
-        for callback in callbacks {
+        for callback in self.snapshot() {
             if let Err(e) = callback.onPayloadStarted(cid as i32) {
                 error!("Error notifying payload start event from VM CID {}: {:?}", cid, e);
             }
@@ -1269,8 +1447,7 @@ impl VirtualMachineCallbacks {
 
     /// Call all registered callbacks to notify that the payload is ready to serve.
     pub fn notify_payload_ready(&self, cid: Cid) {
-        let callbacks = &*self.0.lock().unwrap();
-        for callback in callbacks {
+        for callback in self.snapshot() {
             if let Err(e) = callback.onPayloadReady(cid as i32) {
                 error!("Error notifying payload ready event from VM CID {}: {:?}", cid, e);
             }
@@ -1279,8 +1456,7 @@ impl VirtualMachineCallbacks {
 
     /// Call all registered callbacks to notify that the payload has finished.
     pub fn notify_payload_finished(&self, cid: Cid, exit_code: i32) {
-        let callbacks = &*self.0.lock().unwrap();
-        for callback in callbacks {
+        for callback in self.snapshot() {
             if let Err(e) = callback.onPayloadFinished(cid as i32, exit_code) {
                 error!("Error notifying payload finish event from VM CID {}: {:?}", cid, e);
             }
@@ -1289,8 +1465,7 @@ impl VirtualMachineCallbacks {
 
     /// Call all registered callbacks to say that the VM encountered an error.
     pub fn notify_error(&self, cid: Cid, error_code: ErrorCode, message: &str) {
-        let callbacks = &*self.0.lock().unwrap();
-        for callback in callbacks {
+        for callback in self.snapshot() {
             if let Err(e) = callback.onError(cid as i32, error_code, message) {
                 error!("Error notifying error event from VM CID {}: {:?}", cid, e);
             }
@@ -1299,8 +1474,7 @@ impl VirtualMachineCallbacks {
 
     /// Call all registered callbacks to say that the VM has died.
     pub fn callback_on_died(&self, cid: Cid, reason: DeathReason) {
-        let callbacks = &*self.0.lock().unwrap();
-        for callback in callbacks {
+        for callback in self.snapshot() {
             if let Err(e) = callback.onDied(cid as i32, reason) {
                 error!("Error notifying exit of VM CID {}: {:?}", cid, e);
             }
@@ -1308,9 +1482,20 @@ impl VirtualMachineCallbacks {
     }
 
     /// Add a new callback to the set.
-    fn add(&self, callback: Strong<dyn IVirtualMachineCallback>) {
+    pub fn add(&self, callback: Strong<dyn IVirtualMachineCallback>) {
         self.0.lock().unwrap().push(callback);
     }
+
+    /// Remove a callback from the set, if it is there.
+    pub fn remove(&self, callback: &Strong<dyn IVirtualMachineCallback>) {
+        self.0.lock().unwrap().retain(|c| c.as_binder() != callback.as_binder());
+    }
+
+    /// Returns a copy of the current set of callbacks. Callbacks are called on the copy, so that
+    /// they can be added or removed while a notification is in progress.
+    fn snapshot(&self) -> Vec<Strong<dyn IVirtualMachineCallback>> {
+        self.0.lock().unwrap().clone()
+    }
 }
 
 /// The mutable state of the VirtualizationService. There should only be one instance of this
@@ -1350,6 +1535,8 @@ impl State {
 fn get_state(instance: &VmInstance) -> VirtualMachineState {
     match &*instance.vm_state.lock().unwrap() {
         VmState::NotStarted { .. } => VirtualMachineState::NOT_STARTED,
+        // A VM which is being stopped can't be used any more, whatever its payload is doing.
+        VmState::Running { stopping: true, .. } => VirtualMachineState::DEAD,
         VmState::Running { .. } => match instance.payload_state() {
             PayloadState::Starting => VirtualMachineState::STARTING,
             PayloadState::Started => VirtualMachineState::STARTED,
@@ -1371,6 +1558,33 @@ pub fn clone_file(file: &ParcelFileDescriptor) -> binder::Result<File> {
         .map(File::from)
 }
 
+fn to_console_target(target: &ConsoleTargetParcelable) -> binder::Result<ConsoleTarget> {
+    match target.r#type {
+        ConsoleTargetType::SINK => Ok(ConsoleTarget::Sink),
+        ConsoleTargetType::FILE => match &target.file {
+            Some(file) => Ok(ConsoleTarget::File(clone_file(file)?)),
+            None => Err(anyhow!("Console target of type FILE has no file"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT),
+        },
+        ConsoleTargetType::UNIX_SOCKET => match &target.socketPath {
+            Some(path) => Ok(ConsoleTarget::UnixSocket(path.into())),
+            None => Err(anyhow!("Console target of type UNIX_SOCKET has no socket path"))
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT),
+        },
+        ConsoleTargetType::STDOUT => Ok(ConsoleTarget::Stdout),
+        target_type => Err(anyhow!("Unknown console target type {target_type:?}"))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT),
+    }
+}
+
+fn to_shared_dir(dir: &SharedDirectory) -> binder::Result<SharedDir> {
+    Ok(SharedDir {
+        directory: clone_file(&dir.directory)?,
+        tag: dir.tag.clone(),
+        writable: dir.writable,
+    })
+}
+
 /// Converts an `&Option<ParcelFileDescriptor>` to an `Option<File>` by cloning the file.
 fn maybe_clone_file(file: &Option<ParcelFileDescriptor>) -> binder::Result<Option<File>> {
     file.as_ref().map(clone_file).transpose()
@@ -1450,6 +1664,38 @@ fn extract_gdb_port(config: &VirtualMachineConfig) -> Option<NonZeroU16> {
     }
 }
 
+fn to_restart_policy(config: &VirtualMachineRawConfig) -> binder::Result<RestartPolicy> {
+    match config.restartPolicy {
+        AidlRestartPolicy::NEVER => Ok(RestartPolicy::Never),
+        AidlRestartPolicy::ON_REBOOT => Ok(RestartPolicy::OnReboot),
+        AidlRestartPolicy::ALWAYS => Ok(RestartPolicy::Always {
+            max_retries: config
+                .maxRestarts
+                .try_into()
+                .context("maxRestarts can't be negative")
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?,
+        }),
+        policy => Err(anyhow!("Unknown restart policy {policy:?}"))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT),
+    }
+}
+
+fn to_io_priority_class(priority: &HostPriority) -> binder::Result<Option<IoPriorityClass>> {
+    match priority.ioPriorityClass {
+        AidlIoPriorityClass::DEFAULT => Ok(None),
+        AidlIoPriorityClass::BEST_EFFORT => Ok(Some(IoPriorityClass::BestEffort(
+            priority
+                .ioPriorityLevel
+                .try_into()
+                .context("Invalid I/O priority level")
+                .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT)?,
+        ))),
+        AidlIoPriorityClass::IDLE => Ok(Some(IoPriorityClass::Idle)),
+        class => Err(anyhow!("Unknown I/O priority class {class:?}"))
+            .or_binder_exception(ExceptionCode::ILLEGAL_ARGUMENT),
+    }
+}
+
 fn check_no_vendor_modules(config: &VirtualMachineConfig) -> binder::Result<()> {
     let VirtualMachineConfig::AppConfig(config) = config else { return Ok(()) };
     if let Some(custom_config) = &config.customConfig {
@@ -1567,8 +1813,11 @@ impl IVirtualMachineService for VirtualMachineService {
         let cid = self.cid;
         if let Some(vm) = self.state.lock().unwrap().get_vm(cid) {
             info!("VM with CID {} started payload", cid);
-            vm.update_payload_state(PayloadState::Started)
-                .or_binder_exception(ExceptionCode::ILLEGAL_STATE)?;
+            // The payload state may already have been updated from the log output of the VM.
+            if vm.payload_state() != PayloadState::Started {
+                vm.update_payload_state(PayloadState::Started)
+                    .or_binder_exception(ExceptionCode::ILLEGAL_STATE)?;
+            }
             vm.callbacks.notify_payload_started(cid);
 
             let vm_start_timestamp = vm.vm_metric.lock().unwrap().start_timestamp;
@@ -17,49 +17,56 @@
 use crate::aidl::{remove_temporary_files, Cid, GLOBAL_SERVICE, VirtualMachineCallbacks};
 use crate::atom::{get_num_cpus, write_vm_exited_stats_sync};
 use crate::debug_config::DebugConfig;
+use crate::kernel_params::KernelParams;
 use anyhow::{anyhow, bail, Context, Error, Result};
 use binder::ParcelFileDescriptor;
 use command_fds::CommandFdExt;
 use lazy_static::lazy_static;
-use libc::{sysconf, _SC_CLK_TCK};
-use log::{debug, error, info};
+use libc::{sysconf, _SC_CLK_TCK, _SC_PAGESIZE, _SC_PHYS_PAGES};
+use log::{debug, error, info, warn};
 use semver::{Version, VersionReq};
+use cstr::cstr;
 use nix::{fcntl::OFlag, unistd::pipe2, unistd::Uid, unistd::User};
 use regex::{Captures, Regex};
 use rustutils::system_properties;
-use shared_child::SharedChild;
+use shared_child::{unix::SharedChildExt, SharedChild};
 use std::borrow::Cow;
 use std::cmp::max;
 use std::fmt;
 use std::fs::{read_to_string, File};
-use std::io::{self, Read};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::mem;
 use std::num::{NonZeroU16, NonZeroU32};
-use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
-use std::os::unix::process::ExitStatusExt;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use std::thread::{self, JoinHandle};
 use android_system_virtualizationcommon::aidl::android::system::virtualizationcommon::DeathReason::DeathReason;
 use android_system_virtualizationservice::aidl::android::system::virtualizationservice::{
+    IVirtualMachineCallback::IVirtualMachineCallback,
     MemoryTrimLevel::MemoryTrimLevel,
     VirtualMachineAppConfig::DebugLevel::DebugLevel,
     DisplayConfig::DisplayConfig as DisplayConfigParcelable,
     GpuConfig::GpuConfig as GpuConfigParcelable,
+    VcpuTopology::VcpuTopology as VcpuTopologyParcelable,
 };
 use android_system_virtualizationservice_internal::aidl::android::system::virtualizationservice_internal::IGlobalVmContext::IGlobalVmContext;
 use android_system_virtualizationservice_internal::aidl::android::system::virtualizationservice_internal::IBoundDevice::IBoundDevice;
 use binder::Strong;
 use android_system_virtualmachineservice::aidl::android::system::virtualmachineservice::IVirtualMachineService::IVirtualMachineService;
 use tombstoned_client::{TombstonedConnection, DebuggerdDumpType};
+use vsock::VsockStream;
 use rpcbinder::RpcServer;
 
 /// external/crosvm
 use base::AsRawDescriptor;
 use base::UnixSeqpacketListener;
-use vm_control::{BalloonControlCommand, VmRequest, VmResponse};
+use vm_control::{BalloonControlCommand, SnapshotCommand, VmRequest, VmResponse};
 
 const CROSVM_PATH: &str = "/apex/com.android.virt/bin/crosvm";
 
@@ -69,6 +76,18 @@ const CROSVM_PATH: &str = "/apex/com.android.virt/bin/crosvm";
 /// APEX.
 const CROSVM_PLATFORM_VERSION: &str = "1.0.0";
 
+/// The magic bytes at the start of a qcow2 image.
+const QCOW2_MAGIC: &[u8; 4] = b"QFI\xfb";
+
+/// The magic bytes at the end of the bootconfig trailer of an initrd.
+const BOOTCONFIG_MAGIC: &[u8] = b"#BOOTCONFIG\n";
+
+/// The size of the bootconfig trailer, made of the size, the checksum and the magic bytes.
+const BOOTCONFIG_TRAILER_SIZE: usize = 4 + 4 + BOOTCONFIG_MAGIC.len();
+
+/// The magic number at the start of a flattened device tree blob.
+const FDT_MAGIC: u32 = 0xd00dfeed;
+
 /// The exit status which crosvm returns when it has an error starting a VM.
 const CROSVM_START_ERROR_STATUS: i32 = 1;
 /// The exit status which crosvm returns when a VM requests a reboot.
@@ -82,6 +101,22 @@ const RAMDUMP_RESERVED_MIB: u32 = 17;
 
 const MILLIS_PER_SEC: i64 = 1000;
 
+/// The default minimum memory of a VM. With less than this the guest kernel runs out of memory
+/// while booting, which is hard to tell apart from other boot failures.
+const DEFAULT_MIN_MEMORY_MIB: u32 = 64;
+
+/// The default minimum memory of a protected VM. This is higher than for other VMs because pvmfw
+/// and the swiotlb bounce buffers are carved out of the memory of the guest.
+const DEFAULT_MIN_PROTECTED_MEMORY_MIB: u32 = 128;
+
+/// How long to wait before retrying to connect to the payload, when it isn't listening yet.
+const PAYLOAD_CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Messages which microdroid_manager logs when the payload reaches a state, before notifying it
+/// over vsock.
+const PAYLOAD_LOG_MILESTONES: [(&str, PayloadState); 1] =
+    [("notifying payload started", PayloadState::Started)];
+
 const SYSPROP_CUSTOM_PVMFW_PATH: &str = "hypervisor.pvmfw.path";
 
 /// Serial device for VM console input.
@@ -91,9 +126,9 @@ const CONSOLE_HVC0: &str = "hvc0";
 const CONSOLE_TTYS0: &str = "ttyS0";
 
 lazy_static! {
-    /// If the VM doesn't move to the Started state within this amount time, a hang-up error is
-    /// triggered.
-    static ref BOOT_HANGUP_TIMEOUT: Duration = if nested_virt::is_nested_virtualization().unwrap() {
+    /// Default boot timeout: if the VM doesn't move to the Started state within this amount time,
+    /// a hang-up error is triggered.
+    pub static ref BOOT_HANGUP_TIMEOUT: Duration = if nested_virt::is_nested_virtualization().unwrap() {
         // Nested virtualization is slow, so we need a longer timeout.
         Duration::from_secs(300)
     } else {
@@ -109,31 +144,82 @@ pub struct CrosvmConfig {
     pub bootloader: Option<File>,
     pub kernel: Option<File>,
     pub initrd: Option<File>,
+    /// Bootconfig parameters, such as `androidboot.*` properties, to append to `initrd`.
+    pub bootconfig: Option<File>,
+    /// A directory containing a snapshot taken by [`VmInstance::snapshot`], to restore the VM from
+    /// rather than booting it afresh.
+    pub restore_from_dir: Option<File>,
     pub disks: Vec<DiskFile>,
-    pub params: Option<String>,
+    pub shared_dirs: Vec<SharedDir>,
+    /// Read-only files to map into the guest as virtio-pmem devices. Their sizes must be a
+    /// multiple of the page size.
+    pub pmem_files: Vec<File>,
+    pub params: KernelParams,
     pub protected: bool,
     pub debug_config: DebugConfig,
     pub memory_mib: Option<NonZeroU32>,
+    /// The minimum value of `memory_mib`, if not the default for the kind of VM.
+    pub min_memory_mib: Option<NonZeroU32>,
+    /// Size of the swiotlb bounce buffers of a protected VM, if not the default derived from the
+    /// number of devices.
+    pub swiotlb_mib: Option<NonZeroU32>,
+    /// Whether to add a virtio-balloon device even if the host doesn't support memory reclaim.
+    pub balloon: bool,
     pub cpus: Option<NonZeroU32>,
+    /// How the vCPUs are arranged into sockets, cores and threads. If this is set, `cpus` must be
+    /// set to the total number of vCPUs of the topology.
+    pub cpu_topology: Option<CpuTopology>,
+    /// Pins vCPUs to host CPUs, in the format of crosvm's `--cpu-affinity` flag: either a single
+    /// set of host CPUs for all vCPUs (e.g. `0-3`) or per-vCPU sets (e.g. `0=0,1:1=2,3`).
+    pub cpu_affinity: Option<String>,
     pub host_cpu_topology: bool,
     pub console_out_fd: Option<File>,
+    /// Writers to copy the console output to, in addition to `console_out_fd`. If this is empty,
+    /// crosvm writes the console output to `console_out_fd` directly.
+    pub console_out_sinks: ConsoleSinks,
+    /// Where to connect the console of the VM to, instead of `console_out_fd` and `console_in_fd`.
+    pub console_target: Option<ConsoleTarget>,
     pub console_in_fd: Option<File>,
     pub log_fd: Option<File>,
+    /// Whether to watch the log output of the VM for the payload milestones which Microdroid logs,
+    /// in addition to writing it to `log_fd`.
+    pub watch_payload_log: bool,
     pub ramdump: Option<File>,
     pub indirect_files: Vec<File>,
     pub platform_version: VersionReq,
-    pub detect_hangup: bool,
+    /// Whether to restart crosvm after it exits. It is never restarted if it was stopped through
+    /// the `VmInstance`.
+    pub restart_policy: RestartPolicy,
+    pub sandbox: SandboxConfig,
+    /// If set, the VM is killed with a hang-up error when the payload doesn't start within this
+    /// amount of time.
+    pub boot_timeout: Option<Duration>,
+    /// Port for crosvm to listen on for a gdb connection to debug the guest. Not allowed for
+    /// protected VMs.
+    ///
+    /// crosvm then starts the VM with its vCPUs suspended until the debugger resumes them, so the
+    /// VM starts paused. As the debugger resuming the vCPUs can't be seen from here, the VM is
+    /// reported as paused until [`VmInstance::resume`] is called.
     pub gdb_port: Option<NonZeroU16>,
     pub vfio_devices: Vec<VfioDevice>,
     pub dtbo: Option<File>,
     pub device_tree_overlay: Option<File>,
+    /// Additional device tree overlays to apply to the guest device tree, after
+    /// `device_tree_overlay`.
+    pub device_tree_overlays: Vec<File>,
     pub display_config: Option<DisplayConfig>,
     pub input_device_options: Vec<InputDeviceOption>,
     pub hugepages: bool,
     pub tap: Option<File>,
+    /// MAC address of the guest network interface backed by `tap`, if not crosvm's default.
+    pub tap_mac_address: Option<[u8; 6]>,
     pub virtio_snd_backend: Option<String>,
     pub console_input_device: Option<String>,
     pub boost_uclamp: bool,
+    /// Nice value to run crosvm with, in the range `-20..=19`.
+    pub scheduler_priority: Option<i32>,
+    /// I/O scheduling class to run crosvm with.
+    pub io_priority_class: Option<IoPriorityClass>,
     pub gpu_config: Option<GpuConfig>,
 }
 
@@ -193,11 +279,228 @@ fn try_into_non_zero_u32(value: i32) -> Result<NonZeroU32> {
     NonZeroU32::new(u32_value).ok_or(anyhow!("value should be greater than 0"))
 }
 
+/// When to restart crosvm with the same configuration after it exits.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum RestartPolicy {
+    /// Never restart crosvm.
+    #[default]
+    Never,
+    /// Restart crosvm when the guest reboots itself.
+    OnReboot,
+    /// Restart crosvm whenever it exits, up to `max_retries` times.
+    Always {
+        /// The maximum number of times to restart crosvm.
+        max_retries: u32,
+    },
+}
+
+/// Where to connect the console of a VM to.
+#[derive(Debug)]
+pub enum ConsoleTarget {
+    /// Discard the console output.
+    Sink,
+    /// Write the console output to a file.
+    File(File),
+    /// Listen on a Unix stream socket at the given path, which clients can connect to in order to
+    /// interact with the console.
+    UnixSocket(PathBuf),
+    /// Write the console output to the stdout of crosvm.
+    Stdout,
+}
+
+impl ConsoleTarget {
+    /// Returns the string for a crosvm `--serial` flag, adding the file descriptor for the target
+    /// (if any) to `preserved_fds`.
+    fn to_serial_arg(&self, preserved_fds: &mut Vec<RawFd>) -> String {
+        match self {
+            ConsoleTarget::Sink => "type=sink".to_owned(),
+            ConsoleTarget::File(file) => {
+                format!("type=file,path={}", add_preserved_fd(preserved_fds, file))
+            }
+            ConsoleTarget::UnixSocket(path) => format!("type=unix-stream,path={}", path.display()),
+            ConsoleTarget::Stdout => "type=stdout".to_owned(),
+        }
+    }
+}
+
+/// Whether crosvm sandboxes its device processes.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum SandboxConfig {
+    /// Run all devices in the main crosvm process, without seccomp filters.
+    // TODO(qwandor): Make the sandbox the default.
+    #[default]
+    Disabled,
+    /// Run devices in jailed processes, with the seccomp policies in `policy_dir`.
+    Enabled {
+        /// The directory containing the seccomp policy files for the crosvm devices.
+        policy_dir: PathBuf,
+    },
+}
+
+/// A list of writers to copy the console output of a VM to.
+#[derive(Default)]
+pub struct ConsoleSinks(pub Vec<Box<dyn Write + Send>>);
+
+impl fmt::Debug for ConsoleSinks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ConsoleSinks({} sinks)", self.0.len())
+    }
+}
+
+/// The requests to crosvm over its control socket which are in progress.
+#[derive(Debug, Default)]
+struct ControlRequests {
+    in_flight: u32,
+    /// Whether the VM is being torn down, so no new requests may be started.
+    closed: bool,
+}
+
+/// Keeps a request to crosvm marked as in progress while it is alive.
+struct ControlRequestGuard<'a>(&'a VmInstance);
+
+impl Drop for ControlRequestGuard<'_> {
+    fn drop(&mut self) {
+        self.0.control_requests.lock().unwrap().in_flight -= 1;
+        self.0.control_requests_updated.notify_all();
+    }
+}
+
+/// An error from [`VmInstance::wait`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WaitError {
+    /// The VM hasn't been started, so it can't exit.
+    NotStarted,
+    /// The VM failed to start, so it can't exit.
+    Failed,
+    /// The VM didn't exit within the timeout.
+    Timeout,
+    /// The VM exited, but the exit status of crosvm couldn't be retrieved.
+    NoExitStatus,
+}
+
+impl fmt::Display for WaitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitError::NotStarted => write!(f, "VM has not been started"),
+            WaitError::Failed => write!(f, "VM failed to start"),
+            WaitError::Timeout => write!(f, "Timed out waiting for VM to exit"),
+            WaitError::NoExitStatus => write!(f, "Failed to get exit status of crosvm"),
+        }
+    }
+}
+
+impl std::error::Error for WaitError {}
+
+/// The arrangement of the vCPUs of a VM.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CpuTopology {
+    pub sockets: NonZeroU32,
+    pub cores_per_socket: NonZeroU32,
+    pub threads_per_core: NonZeroU32,
+}
+
+impl CpuTopology {
+    pub fn new(raw_config: &VcpuTopologyParcelable) -> Result<CpuTopology> {
+        let sockets = try_into_non_zero_u32(raw_config.sockets)?;
+        let cores_per_socket = try_into_non_zero_u32(raw_config.coresPerSocket)?;
+        let threads_per_core = try_into_non_zero_u32(raw_config.threadsPerCore)?;
+        Ok(CpuTopology { sockets, cores_per_socket, threads_per_core })
+    }
+
+    /// Returns the total number of vCPUs, saturating at `u32::MAX`.
+    pub fn num_cpus(&self) -> u32 {
+        self.sockets
+            .get()
+            .saturating_mul(self.cores_per_socket.get())
+            .saturating_mul(self.threads_per_core.get())
+    }
+
+    /// Returns the value of crosvm's `--cpus` flag for this topology. crosvm has no notion of
+    /// sockets or SMT threads, so each socket is rendered as a cluster of all the vCPUs in it.
+    fn to_crosvm_arg(self) -> String {
+        let cpus_per_socket = self.num_cpus() / self.sockets.get();
+        let clusters = (0..self.sockets.get())
+            .map(|socket| {
+                let first = socket * cpus_per_socket;
+                let cpus: Vec<_> =
+                    (first..first + cpus_per_socket).map(|c| c.to_string()).collect();
+                format!("[{}]", cpus.join(","))
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        format!("num-cores={},clusters=[{clusters}]", self.num_cpus())
+    }
+}
+
+/// An I/O scheduling class, as set by `ionice`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IoPriorityClass {
+    /// Best-effort scheduling, with a priority level from 0 (highest) to 7 (lowest).
+    BestEffort(u8),
+    /// Only gets disk time when no other process needs it.
+    Idle,
+}
+
+impl IoPriorityClass {
+    /// Returns the value to pass to `ioprio_set`.
+    fn ioprio(self) -> libc::c_int {
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        match self {
+            IoPriorityClass::BestEffort(level) => (2 << IOPRIO_CLASS_SHIFT) | level as libc::c_int,
+            IoPriorityClass::Idle => 3 << IOPRIO_CLASS_SHIFT,
+        }
+    }
+}
+
 /// A disk image to pass to crosvm for a VM.
 #[derive(Debug)]
 pub struct DiskFile {
     pub image: File,
     pub writable: bool,
+    pub format: DiskFormat,
+    pub cache: CacheMode,
+    /// Whether crosvm should use io_uring rather than epoll to drive I/O to the image.
+    pub block_async: bool,
+}
+
+/// How writes by the guest to a disk image reach the host's storage.
+///
+/// Only `WritesThrough` makes a completed guest write survive a host crash by itself. In the other
+/// modes, writes are only durable once the guest has flushed the disk, which makes crosvm sync the
+/// image to storage.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CacheMode {
+    /// Writes go to the host page cache and are written back to storage later, so unflushed writes
+    /// may be lost if the host crashes. This is the default.
+    WritesBack,
+    /// The image is opened with `O_DSYNC`, so each write completes only once it has reached
+    /// storage. This is the slowest mode. It isn't supported for composite images, whose partition
+    /// images crosvm opens itself.
+    WritesThrough,
+    /// The image is opened with `O_DIRECT`, bypassing the host page cache. The host then doesn't
+    /// hold unflushed guest writes in memory, but the storage device's own cache may.
+    None,
+}
+
+/// The format of a disk image. crosvm detects the format from the contents of the image, so this
+/// is only used to check that the image is what the caller expects.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DiskFormat {
+    /// A raw image, or a composite image made of raw partitions.
+    Raw,
+    /// A qcow2 image.
+    Qcow2,
+}
+
+/// A host directory to share with a VM over virtio-fs.
+#[derive(Debug)]
+pub struct SharedDir {
+    /// The directory on the host.
+    pub directory: File,
+    /// The tag which the guest uses to mount the directory.
+    pub tag: String,
+    /// Whether the guest may modify the directory.
+    pub writable: bool,
 }
 
 /// virtio-input device configuration from `external/crosvm/src/crosvm/config.rs`
@@ -239,6 +542,14 @@ pub enum VmState {
         child: Arc<SharedChild>,
         /// The thread waiting for crosvm to finish.
         monitor_vm_exit_thread: Option<JoinHandle<()>>,
+        /// Whether the VM has been asked to stop, or crosvm has exited and the VM is being torn
+        /// down.
+        stopping: bool,
+        /// Whether the vCPUs of the VM have been suspended by [`VmInstance::pause`], or are
+        /// waiting for a debugger to connect.
+        paused: bool,
+        /// The configuration to restart crosvm with, if its restart policy allows it.
+        config: Option<Box<CrosvmConfig>>,
     },
     /// The VM died or was killed.
     Dead,
@@ -246,6 +557,26 @@ pub enum VmState {
     Failed,
 }
 
+/// A summary of the lifecycle of the VM, as returned by [`VmInstance::state`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VmLifecycle {
+    /// The VM has not yet tried to start.
+    NotStarted,
+    /// crosvm is running the VM.
+    Running,
+    /// The VM has been paused, i.e. its vCPUs are suspended.
+    Paused,
+    /// The VM has been asked to stop, or crosvm has exited and the VM is being torn down.
+    Stopping,
+    /// The VM died or was killed, and has been torn down.
+    Dead {
+        /// The exit status of crosvm, if it could be retrieved.
+        exit_status: Option<ExitStatus>,
+    },
+    /// The VM failed to start.
+    Failed,
+}
+
 /// RSS values of VM and CrosVM process itself.
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Rss {
@@ -253,11 +584,35 @@ pub struct Rss {
     pub crosvm: i64,
 }
 
+/// Memory statistics reported by the guest through the virtio balloon.
+#[derive(Copy, Clone, Debug)]
+pub struct BalloonStats {
+    /// The current size of the balloon, i.e. how much memory is reclaimed from the guest, in bytes.
+    pub balloon_actual: u64,
+    /// The amount of memory available to the guest, in bytes, if reported.
+    pub available_memory: Option<u64>,
+    /// The total amount of memory of the guest, in bytes, if reported.
+    pub total_memory: Option<u64>,
+}
+
+/// Current memory usage of a VM, as returned by [`VmInstance::memory_stats`].
+#[derive(Copy, Clone, Debug)]
+pub struct MemoryStats {
+    /// RSS of the guest memory and of crosvm as a whole, in KiB.
+    pub rss: Rss,
+    /// What the guest reports through the virtio balloon, if it does.
+    pub balloon: Option<BalloonStats>,
+}
+
 /// Metrics regarding the VM.
 #[derive(Debug, Default)]
 pub struct VmMetric {
     /// Recorded timestamp when the VM is started.
     pub start_timestamp: Option<SystemTime>,
+    /// When the VM was started, on the monotonic clock.
+    pub start_instant: Option<Instant>,
+    /// When crosvm exited, on the monotonic clock.
+    pub exit_instant: Option<Instant>,
     /// Update most recent guest_time periodically from /proc/[crosvm pid]/stat while VM is
     /// running.
     pub cpu_guest_time: Option<i64>,
@@ -272,38 +627,40 @@ impl VmState {
     fn start(&mut self, instance: Arc<VmInstance>) -> Result<(), Error> {
         let state = mem::replace(self, VmState::Failed);
         if let VmState::NotStarted { config } = state {
-            let config = *config;
-            let detect_hangup = config.detect_hangup;
+            let mut config = *config;
+            // This is done once, rather than every time crosvm is restarted with the same config.
+            config.append_bootconfig_to_initrd()?;
+            if config.watch_payload_log {
+                // Likewise, the same thread keeps reading the log output after restarts.
+                instance.watch_payload_log(&mut config)?;
+            }
             let (failure_pipe_read, failure_pipe_write) = create_pipe()?;
             let vfio_devices = config.vfio_devices.clone();
             let tap =
                 if let Some(tap_file) = &config.tap { Some(tap_file.try_clone()?) } else { None };
 
             // If this fails and returns an error, `self` will be left in the `Failed` state.
-            let child =
-                Arc::new(run_vm(config, &instance.crosvm_control_socket_path, failure_pipe_write)?);
-
-            let instance_monitor_status = instance.clone();
-            let child_monitor_status = child.clone();
-            thread::spawn(move || {
-                instance_monitor_status.clone().monitor_vm_status(child_monitor_status);
-            });
-
-            let child_clone = child.clone();
-            let instance_clone = instance.clone();
-            let monitor_vm_exit_thread = Some(thread::spawn(move || {
-                instance_clone.monitor_vm_exit(child_clone, failure_pipe_read, vfio_devices, tap);
-            }));
-
-            if detect_hangup {
-                let child_clone = child.clone();
-                thread::spawn(move || {
-                    instance.monitor_payload_hangup(child_clone);
-                });
-            }
+            let child = Arc::new(run_vm(
+                &mut config,
+                &instance.crosvm_control_socket_path,
+                failure_pipe_write,
+            )?);
+
+            let monitor_vm_exit_thread = Some(instance.spawn_monitors(
+                &child,
+                failure_pipe_read,
+                vfio_devices,
+                tap,
+                config.boot_timeout,
+            ));
+
+            let paused = config.gdb_port.is_some();
+            // The config is only needed after starting if crosvm may have to be restarted.
+            let config = (config.restart_policy != RestartPolicy::Never).then(|| Box::new(config));
 
             // If it started correctly, update the state.
-            *self = VmState::Running { child, monitor_vm_exit_thread };
+            *self =
+                VmState::Running { child, monitor_vm_exit_thread, stopping: false, paused, config };
             Ok(())
         } else {
             *self = state;
@@ -361,6 +718,21 @@ pub struct VmInstance {
     payload_state: Mutex<PayloadState>,
     /// Represents the condition that payload_state was updated
     payload_state_updated: Condvar,
+    /// The exit status of crosvm, set once it has been reaped. Only updated while `vm_state` is
+    /// locked.
+    exit_status: Mutex<Option<ExitStatus>>,
+    /// Requests to crosvm over its control socket which are in progress.
+    control_requests: Mutex<ControlRequests>,
+    /// Represents the condition that control_requests was updated
+    control_requests_updated: Condvar,
+    /// Whether the VM has died and been torn down.
+    torn_down: Mutex<bool>,
+    /// Represents the condition that torn_down was set
+    torn_down_updated: Condvar,
+    /// How many times crosvm has been restarted according to the restart policy of the VM.
+    restart_count: AtomicU32,
+    /// How many snapshots of the VM have been requested, to name their temporary directories.
+    snapshot_count: AtomicU32,
     /// The human readable name of requester_uid
     requester_uid_name: String,
 }
@@ -408,6 +780,13 @@ impl VmInstance {
             vm_metric: Mutex::new(Default::default()),
             payload_state: Mutex::new(PayloadState::Starting),
             payload_state_updated: Condvar::new(),
+            exit_status: Mutex::new(None),
+            control_requests: Mutex::new(Default::default()),
+            control_requests_updated: Condvar::new(),
+            torn_down: Mutex::new(false),
+            torn_down_updated: Condvar::new(),
+            restart_count: AtomicU32::new(0),
+            snapshot_count: AtomicU32::new(0),
             requester_uid_name,
         };
         info!("{} created", &instance);
@@ -419,6 +798,7 @@ impl VmInstance {
     pub fn start(self: &Arc<Self>) -> Result<(), Error> {
         let mut vm_metric = self.vm_metric.lock().unwrap();
         vm_metric.start_timestamp = Some(SystemTime::now());
+        vm_metric.start_instant = Some(Instant::now());
         let ret = self.vm_state.lock().unwrap().start(self.clone());
         if ret.is_ok() {
             info!("{} started", &self);
@@ -430,7 +810,7 @@ impl VmInstance {
     /// handles the event by updating the state, noityfing the event to clients by calling
     /// callbacks, and removing temporary files for the VM.
     fn monitor_vm_exit(
-        &self,
+        self: &Arc<Self>,
         child: Arc<SharedChild>,
         mut failure_pipe_read: File,
         vfio_devices: Vec<VfioDevice>,
@@ -449,12 +829,36 @@ impl VmInstance {
             }
         }
 
+        if self.restart_if_allowed(&result, &vfio_devices, &tap) {
+            // The thread monitoring the new crosvm process takes over from here.
+            return;
+        }
+        self.vm_metric.lock().unwrap().exit_instant = Some(Instant::now());
+
+        // Until the teardown below is finished the VM is stopping rather than dead.
         let mut vm_state = self.vm_state.lock().unwrap();
-        *vm_state = VmState::Dead;
-        // Ensure that the mutex is released before calling the callbacks.
+        *self.exit_status.lock().unwrap() = result.as_ref().ok().copied();
+        if let VmState::Running { stopping, .. } = &mut *vm_state {
+            *stopping = true;
+        }
         drop(vm_state);
         info!("{} exited", &self);
 
+        // Requests in progress, in particular snapshots, may still be writing to the temporary
+        // directory, so let them finish before tearing down the VM.
+        self.finish_control_requests();
+
+        // The control socket is useless once crosvm is gone, so don't leave it around for clients
+        // to try to talk to.
+        if let Err(e) = std::fs::remove_file(&self.crosvm_control_socket_path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                error!(
+                    "Error removing control socket {:?}: {}",
+                    self.crosvm_control_socket_path, e
+                );
+            }
+        }
+
         // Read the pipe to see if any failure reason is written
         let mut failure_reason = String::new();
         match failure_pipe_read.read_to_string(&mut failure_reason) {
@@ -475,9 +879,37 @@ impl VmInstance {
 
         self.handle_ramdump().unwrap_or_else(|e| error!("Error handling ramdump: {}", e));
 
+        if let Some((_, info)) = failure_reason.split_once('|') {
+            error!("Failure info: {info}");
+        }
+        if let Ok(status) = &result {
+            if let CrosvmExit::CrashSignal(signal) = CrosvmExit::from(status) {
+                error!("crosvm crashed with signal {signal}");
+            }
+        }
         let death_reason = death_reason(&result, &failure_reason);
         let exit_signal = exit_signal(&result);
 
+        // Delete temporary files. The folder itself is removed by VirtualizationServiceInternal.
+        remove_temporary_files(&self.temporary_directory).unwrap_or_else(|e| {
+            error!("Error removing temporary files from {:?}: {}", self.temporary_directory, e);
+        });
+
+        if let Some(tap_file) = tap {
+            GLOBAL_SERVICE
+                .deleteTapInterface(&ParcelFileDescriptor::new(OwnedFd::from(tap_file)))
+                .unwrap_or_else(|e| {
+                    error!("Error deleting TAP interface: {e:?}");
+                });
+        }
+
+        drop(vfio_devices); // Cleanup devices.
+
+        let mut vm_state = self.vm_state.lock().unwrap();
+        *vm_state = VmState::Dead;
+        // Ensure that the mutex is released before calling the callbacks.
+        drop(vm_state);
+
         self.callbacks.callback_on_died(self.cid, death_reason);
 
         let vm_metric = self.vm_metric.lock().unwrap();
@@ -488,30 +920,194 @@ impl VmInstance {
             exit_signal,
             &vm_metric,
         );
+        drop(vm_metric);
 
-        // Delete temporary files. The folder itself is removed by VirtualizationServiceInternal.
-        remove_temporary_files(&self.temporary_directory).unwrap_or_else(|e| {
-            error!("Error removing temporary files from {:?}: {}", self.temporary_directory, e);
+        *self.torn_down.lock().unwrap() = true;
+        self.torn_down_updated.notify_all();
+    }
+
+    /// Spawns the threads monitoring a newly spawned crosvm process, and returns the one waiting
+    /// for it to exit.
+    fn spawn_monitors(
+        self: &Arc<Self>,
+        child: &Arc<SharedChild>,
+        failure_pipe_read: File,
+        vfio_devices: Vec<VfioDevice>,
+        tap: Option<File>,
+        boot_timeout: Option<Duration>,
+    ) -> JoinHandle<()> {
+        let instance_monitor_status = self.clone();
+        let child_monitor_status = child.clone();
+        thread::spawn(move || {
+            instance_monitor_status.clone().monitor_vm_status(child_monitor_status);
         });
 
-        if let Some(tap_file) = tap {
-            GLOBAL_SERVICE
-                .deleteTapInterface(&ParcelFileDescriptor::new(OwnedFd::from(tap_file)))
-                .unwrap_or_else(|e| {
-                    error!("Error deleting TAP interface: {e:?}");
-                });
+        if let Some(boot_timeout) = boot_timeout {
+            let instance_clone = self.clone();
+            let child_clone = child.clone();
+            thread::spawn(move || {
+                instance_clone.monitor_payload_hangup(child_clone, boot_timeout);
+            });
         }
 
-        drop(vfio_devices); // Cleanup devices.
+        let child_clone = child.clone();
+        let instance_clone = self.clone();
+        thread::spawn(move || {
+            instance_clone.monitor_vm_exit(child_clone, failure_pipe_read, vfio_devices, tap);
+        })
+    }
+
+    /// Restarts crosvm after it exited with `result`, if the restart policy of the VM allows it
+    /// and it wasn't stopped on purpose. Returns whether it was restarted.
+    fn restart_if_allowed(
+        self: &Arc<Self>,
+        result: &Result<ExitStatus, io::Error>,
+        vfio_devices: &[VfioDevice],
+        tap: &Option<File>,
+    ) -> bool {
+        let Ok(status) = result else {
+            return false;
+        };
+        // crosvm is respawned without holding the lock, so that the VM can still be queried and
+        // stopped in the meantime. Only this thread takes the config out of the state.
+        let (old_child, mut config) = {
+            let vm_state = &mut *self.vm_state.lock().unwrap();
+            let VmState::Running { child, stopping: false, config, .. } = vm_state else {
+                return false;
+            };
+            let restart_count = self.restart_count.load(Ordering::Relaxed);
+            let restart = match config.as_ref().map(|config| &config.restart_policy) {
+                None | Some(RestartPolicy::Never) => false,
+                Some(RestartPolicy::OnReboot) => status.code() == Some(CROSVM_REBOOT_STATUS),
+                Some(RestartPolicy::Always { max_retries }) => restart_count < *max_retries,
+            };
+            if !restart {
+                return false;
+            }
+            (child.clone(), config.take().unwrap())
+        };
+
+        info!("Restarting {} after crosvm exited with status {}", &self, status);
+        let (new_child, failure_pipe_read, tap) = match self.respawn(&mut config, tap) {
+            Ok(respawned) => respawned,
+            Err(e) => {
+                error!("Failed to restart {}: {:?}", &self, e);
+                return false;
+            }
+        };
+
+        let mut vm_state = self.vm_state.lock().unwrap();
+        match &mut *vm_state {
+            VmState::Running {
+                child,
+                monitor_vm_exit_thread,
+                stopping: false,
+                paused,
+                config: state_config,
+            } if Arc::ptr_eq(child, &old_child) => {
+                *paused = config.gdb_port.is_some();
+                // Replacing the handle to this thread detaches it, which is fine as it returns as
+                // soon as this does.
+                *monitor_vm_exit_thread = Some(self.spawn_monitors(
+                    &new_child,
+                    failure_pipe_read,
+                    vfio_devices.to_vec(),
+                    tap,
+                    config.boot_timeout,
+                ));
+                *child = new_child;
+                *state_config = Some(config);
+                self.restart_count.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            _ => {
+                drop(vm_state);
+                info!("{} was stopped while crosvm was restarting", &self);
+                if let Err(e) = new_child.kill().and_then(|()| new_child.wait()) {
+                    error!("Error killing restarted crosvm({}): {}", new_child.id(), e);
+                }
+                false
+            }
+        }
+    }
+
+    /// Spawns a new crosvm process for the VM with the given config. Returns it along with the
+    /// read end of its failure pipe and the clone of `tap` to pass to [`Self::spawn_monitors`].
+    fn respawn(
+        &self,
+        config: &mut CrosvmConfig,
+        tap: &Option<File>,
+    ) -> Result<(Arc<SharedChild>, File, Option<File>), Error> {
+        let (failure_pipe_read, failure_pipe_write) = create_pipe()?;
+        let tap = tap.as_ref().map(File::try_clone).transpose()?;
+        // The control socket of the previous crosvm process is still there.
+        if let Err(e) = std::fs::remove_file(&self.crosvm_control_socket_path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(e).context("Failed to remove control socket");
+            }
+        }
+        let child = Arc::new(run_vm(config, &self.crosvm_control_socket_path, failure_pipe_write)?);
+
+        // The payload of the new guest starts from scratch.
+        *self.payload_state.lock().unwrap() = PayloadState::Starting;
+        Ok((child, failure_pipe_read, tap))
+    }
+
+    /// Makes crosvm write the log output of the VM to a pipe instead, read by a thread which copies
+    /// it to `log_fd` and updates the payload state when the log shows that the payload has reached
+    /// a milestone.
+    ///
+    /// This catches a payload which has started but whose notification over vsock is delayed, so
+    /// that it isn't treated as hung.
+    fn watch_payload_log(self: &Arc<Self>, config: &mut CrosvmConfig) -> Result<(), Error> {
+        let log_fd = config.log_fd.take();
+        let (log_read, log_write) = create_pipe()?;
+        // The config may be kept for restarts, along with the write end of the pipe, so the thread
+        // mustn't keep the instance alive.
+        let instance = Arc::downgrade(self);
+        thread::spawn(move || {
+            read_log_lines(log_read, log_fd, |line| {
+                let (Some(state), Some(instance)) = (payload_milestone(line), instance.upgrade())
+                else {
+                    return;
+                };
+                if instance.payload_state() < state {
+                    info!("{} logged payload milestone {:?}", &instance, state);
+                    // The payload may have notified the state over vsock in the meantime.
+                    if let Err(e) = instance.update_payload_state(state) {
+                        debug!("Ignoring payload milestone: {e:?}");
+                    }
+                }
+            })
+        });
+        config.log_fd = Some(log_write);
+        Ok(())
+    }
+
+    /// Registers a callback to be notified of events of this VM. Any number of callbacks may be
+    /// registered, e.g. by independent clients of the same VM.
+    pub fn add_callback(&self, callback: Strong<dyn IVirtualMachineCallback>) {
+        self.callbacks.add(callback);
+    }
+
+    /// Unregisters a callback previously passed to `add_callback`. This is safe to call from
+    /// within a callback, as notifications are sent to a snapshot of the registered callbacks.
+    pub fn remove_callback(&self, callback: &Strong<dyn IVirtualMachineCallback>) {
+        self.callbacks.remove(callback);
+    }
+
+    /// Returns how many times crosvm has been restarted according to the restart policy of the VM.
+    pub fn restart_count(&self) -> u32 {
+        self.restart_count.load(Ordering::Relaxed)
     }
 
     /// Waits until payload is started, or timeout expires. When timeout occurs, kill
     /// the VM to prevent indefinite hangup and update the payload_state accordingly.
-    fn monitor_payload_hangup(&self, child: Arc<SharedChild>) {
+    fn monitor_payload_hangup(&self, child: Arc<SharedChild>, boot_timeout: Duration) {
         debug!("Starting to monitor hangup for Microdroid({})", child.id());
         let (state, result) = self
             .payload_state_updated
-            .wait_timeout_while(self.payload_state.lock().unwrap(), *BOOT_HANGUP_TIMEOUT, |s| {
+            .wait_timeout_while(self.payload_state.lock().unwrap(), boot_timeout, |s| {
                 *s < PayloadState::Started
             })
             .unwrap();
@@ -521,7 +1117,7 @@ impl VmInstance {
             error!(
                 "Microdroid({}) failed to start payload within {} secs timeout. Shutting down.",
                 child.id(),
-                BOOT_HANGUP_TIMEOUT.as_secs()
+                boot_timeout.as_secs()
             );
             self.update_payload_state(PayloadState::Hangup).unwrap();
             if let Err(e) = self.kill() {
@@ -531,13 +1127,16 @@ impl VmInstance {
     }
 
     fn monitor_vm_status(&self, child: Arc<SharedChild>) {
-        let pid = child.id();
+        let pid = child.id() as i32;
 
         loop {
             {
                 // Check VM state
                 let vm_state = &*self.vm_state.lock().unwrap();
-                if let VmState::Dead = vm_state {
+                // Stop when crosvm has been restarted too, as the new child has its own monitor.
+                if !matches!(vm_state, VmState::Running { stopping: false, child: current, .. }
+                    if Arc::ptr_eq(current, &child))
+                {
                     break;
                 }
 
@@ -565,6 +1164,62 @@ impl VmInstance {
         }
     }
 
+    /// Returns the current lifecycle state of the VM.
+    pub fn state(&self) -> VmLifecycle {
+        match &*self.vm_state.lock().unwrap() {
+            VmState::NotStarted { .. } => VmLifecycle::NotStarted,
+            VmState::Running { stopping: false, paused: false, .. } => VmLifecycle::Running,
+            VmState::Running { stopping: false, paused: true, .. } => VmLifecycle::Paused,
+            VmState::Running { stopping: true, .. } => VmLifecycle::Stopping,
+            VmState::Dead => VmLifecycle::Dead { exit_status: *self.exit_status.lock().unwrap() },
+            VmState::Failed => VmLifecycle::Failed,
+        }
+    }
+
+    /// Returns the PID of the crosvm process, if it is running.
+    pub fn pid(&self) -> Option<i32> {
+        match &*self.vm_state.lock().unwrap() {
+            VmState::Running { child, .. } => Some(child.id() as i32),
+            _ => None,
+        }
+    }
+
+    /// Returns the current memory usage of the VM, if it is running.
+    pub fn memory_stats(&self) -> Result<MemoryStats, Error> {
+        let pid = self.pid().context("VM is not running")?;
+        let rss = get_rss(pid)?;
+        let balloon = self.balloon_stats().unwrap_or_else(|e| {
+            warn!("Failed to get balloon stats of {}: {:?}", &self, e);
+            None
+        });
+        Ok(MemoryStats { rss, balloon })
+    }
+
+    /// Returns the exit status of crosvm, or `None` if the VM hasn't died yet or the status
+    /// couldn't be retrieved.
+    ///
+    /// This can be used to tell a clean shutdown of the guest (exit code 0) apart from a crash of
+    /// crosvm or it being killed by a signal.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        let vm_state = self.vm_state.lock().unwrap();
+        if matches!(*vm_state, VmState::Dead) {
+            *self.exit_status.lock().unwrap()
+        } else {
+            None
+        }
+    }
+
+    /// Returns how long the VM has been running, or ran for until it died. This is zero if the VM
+    /// hasn't been started.
+    pub fn uptime(&self) -> Duration {
+        let vm_metric = self.vm_metric.lock().unwrap();
+        match (vm_metric.start_instant, vm_metric.exit_instant) {
+            (Some(start), Some(exit)) => exit.saturating_duration_since(start),
+            (Some(start), None) => start.elapsed(),
+            (None, _) => Duration::ZERO,
+        }
+    }
+
     /// Returns the last reported state of the VM payload.
     pub fn payload_state(&self) -> PayloadState {
         *self.payload_state.lock().unwrap()
@@ -584,14 +1239,125 @@ impl VmInstance {
         }
     }
 
+    /// Connects to `port` of the VM, retrying until `timeout` has elapsed in case the payload
+    /// isn't listening yet.
+    ///
+    /// The read and write timeouts of the returned stream are also set to `timeout`, so that an
+    /// unresponsive payload can't block its users forever.
+    pub fn connect_payload_stream(&self, port: u32, timeout: Duration) -> Result<VsockStream> {
+        if timeout.is_zero() {
+            bail!("Timeout to connect to the payload must not be zero");
+        }
+        let deadline = Instant::now() + timeout;
+        let stream = loop {
+            match VsockStream::connect_with_cid_port(self.cid, port) {
+                Ok(stream) => break stream,
+                Err(e) if Instant::now() < deadline => {
+                    debug!("Retrying to connect to port {port} of VM with CID {}: {e}", self.cid);
+                    thread::sleep(PAYLOAD_CONNECT_RETRY_INTERVAL);
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("Failed to connect to port {port} within {timeout:?}")
+                    })
+                }
+            }
+        };
+        set_stream_timeouts(&stream, Some(timeout))?;
+        Ok(stream)
+    }
+
+    /// Asks crosvm over the control socket to shut down the VM cleanly, if it is running.
+    ///
+    /// If crosvm doesn't accept the request, it is stopped as by [`VmInstance::kill_with_grace`]
+    /// instead. If it hasn't exited within `timeout`, it is killed.
+    pub fn stop(&self, timeout: Duration) -> Result<(), Error> {
+        let child = self.mark_stopping()?;
+        let id = child.id();
+        debug!("Stopping crosvm({})", id);
+        match self.handle_request(&VmRequest::Exit) {
+            Ok(VmResponse::Ok) => self.wait_or_kill(timeout),
+            e => {
+                error!("crosvm({id}) refused to stop: {e:?}. Terminating it instead.");
+                self.kill_with_grace(timeout)
+            }
+        }
+    }
+
+    /// Sends `SIGTERM` to crosvm, if it is running, which makes it flush any writable disks and
+    /// exit. If it hasn't exited within `timeout`, it is killed.
+    pub fn kill_with_grace(&self, timeout: Duration) -> Result<(), Error> {
+        let child = self.mark_stopping()?;
+        let id = child.id();
+        debug!("Terminating crosvm({})", id);
+        if let Err(e) = child.send_signal(libc::SIGTERM) {
+            error!("Error sending SIGTERM to crosvm({id}): {e}. Killing it instead.");
+            return self.kill();
+        }
+        self.wait_or_kill(timeout)
+    }
+
+    /// Marks the VM as stopping and returns its crosvm process, if it is running.
+    fn mark_stopping(&self) -> Result<Arc<SharedChild>, Error> {
+        let vm_state = &mut *self.vm_state.lock().unwrap();
+        if let VmState::Running { child, stopping, .. } = vm_state {
+            *stopping = true;
+            Ok(child.clone())
+        } else {
+            bail!("VM is not running")
+        }
+    }
+
+    /// Waits for crosvm to exit and the VM to be torn down, killing crosvm if it hasn't exited
+    /// within `timeout`.
+    fn wait_or_kill(&self, timeout: Duration) -> Result<(), Error> {
+        if let Err(WaitError::Timeout) = self.wait(Some(timeout)) {
+            error!("{} didn't stop within {timeout:?}. Killing it instead.", self);
+            return self.kill();
+        }
+
+        self.vm_context.vm_server.shutdown()?;
+
+        Ok(())
+    }
+
+    /// Blocks until the VM has died and been torn down, or `timeout` expires. Returns the exit
+    /// status of crosvm.
+    ///
+    /// Once this returns successfully, the temporary files of the VM have been removed and all
+    /// callbacks have been notified of its death.
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<ExitStatus, WaitError> {
+        match *self.vm_state.lock().unwrap() {
+            VmState::NotStarted { .. } => return Err(WaitError::NotStarted),
+            VmState::Failed => return Err(WaitError::Failed),
+            _ => {}
+        }
+        let torn_down = self.torn_down.lock().unwrap();
+        let torn_down = match timeout {
+            Some(timeout) => {
+                let (torn_down, result) = self
+                    .torn_down_updated
+                    .wait_timeout_while(torn_down, timeout, |torn_down| !*torn_down)
+                    .unwrap();
+                if result.timed_out() {
+                    return Err(WaitError::Timeout);
+                }
+                torn_down
+            }
+            None => self.torn_down_updated.wait_while(torn_down, |torn_down| !*torn_down).unwrap(),
+        };
+        drop(torn_down);
+        self.exit_status.lock().unwrap().ok_or(WaitError::NoExitStatus)
+    }
+
     /// Kills the crosvm instance, if it is running.
     pub fn kill(&self) -> Result<(), Error> {
         let monitor_vm_exit_thread = {
             let vm_state = &mut *self.vm_state.lock().unwrap();
-            if let VmState::Running { child, monitor_vm_exit_thread } = vm_state {
+            if let VmState::Running { child, monitor_vm_exit_thread, stopping, .. } = vm_state {
                 let id = child.id();
                 debug!("Killing crosvm({})", id);
-                // TODO: Talk to crosvm to shutdown cleanly.
+                *stopping = true;
                 child.kill().with_context(|| format!("Error killing crosvm({id}) instance"))?;
                 monitor_vm_exit_thread.take()
             } else {
@@ -610,46 +1376,175 @@ impl VmInstance {
         Ok(())
     }
 
+    /// Suspends the vCPUs of the VM, without tearing it down. The VM can be resumed with
+    /// [`VmInstance::resume`].
+    pub fn pause(&self) -> Result<(), Error> {
+        self.set_paused(true)
+    }
+
+    /// Sends a request to crosvm over its control socket. The VM isn't torn down while the
+    /// request is in progress.
+    fn handle_request(&self, request: &VmRequest) -> vm_control::client::HandleRequestResult {
+        let _guard = self.start_control_request().ok_or(())?;
+        vm_control::client::handle_request(request, &self.crosvm_control_socket_path)
+    }
+
+    /// Marks a request to crosvm as in progress until the returned guard is dropped, or returns
+    /// `None` if the VM is already being torn down.
+    fn start_control_request(&self) -> Option<ControlRequestGuard<'_>> {
+        let mut control_requests = self.control_requests.lock().unwrap();
+        if control_requests.closed {
+            return None;
+        }
+        control_requests.in_flight += 1;
+        Some(ControlRequestGuard(self))
+    }
+
+    /// Waits for all requests to crosvm in progress to finish, and refuses new ones.
+    fn finish_control_requests(&self) {
+        let mut control_requests = self
+            .control_requests_updated
+            .wait_while(self.control_requests.lock().unwrap(), |r| r.in_flight > 0)
+            .unwrap();
+        control_requests.closed = true;
+    }
+
+    /// Resumes the vCPUs of a VM paused by [`VmInstance::pause`].
+    pub fn resume(&self) -> Result<(), Error> {
+        self.set_paused(false)
+    }
+
+    fn set_paused(&self, pause: bool) -> Result<(), Error> {
+        let child = match &*self.vm_state.lock().unwrap() {
+            VmState::Running { stopping: false, paused, .. } if *paused == pause => {
+                bail!("VM is already {}", if pause { "paused" } else { "resumed" })
+            }
+            VmState::Running { stopping: false, child, .. } => child.clone(),
+            _ => bail!("VM is not running"),
+        };
+        // Don't hold the lock while waiting for crosvm, as that would block e.g. `state()`.
+        let request = if pause { VmRequest::SuspendVcpus } else { VmRequest::ResumeVcpus };
+        match self.handle_request(&request) {
+            Ok(VmResponse::Ok) => {}
+            e => bail!("Error sending {:?}: {:?}", request, e),
+        }
+        match &mut *self.vm_state.lock().unwrap() {
+            // crosvm may have exited, or even been restarted, since the request was sent.
+            VmState::Running { child: current, paused, .. } if Arc::ptr_eq(current, &child) => {
+                *paused = pause;
+                Ok(())
+            }
+            _ => bail!("VM stopped while being {}", if pause { "paused" } else { "resumed" }),
+        }
+    }
+
+    /// Takes a snapshot of the VM into the directory `dest`, which the caller opened.
+    ///
+    /// crosvm writes the snapshot to the temporary directory of the VM, from where it is copied
+    /// into `dest`, so that clients can't make us write anywhere they can't already write to. The
+    /// VM is paused for the duration of the snapshot. Afterwards it is resumed if `resume` is true
+    /// and it was running before, and left paused otherwise.
+    pub fn snapshot(&self, dest: &File, resume: bool) -> Result<(), Error> {
+        let count = self.snapshot_count.fetch_add(1, Ordering::Relaxed);
+        let path = self.temporary_directory.join(format!("snapshot-{count}"));
+        // Don't let the VM be torn down between pausing it and resuming it.
+        let _guard = self.start_control_request().context("VM is not running")?;
+        let was_paused = match self.state() {
+            VmLifecycle::Running => false,
+            VmLifecycle::Paused => true,
+            _ => bail!("VM is not running"),
+        };
+        if !was_paused {
+            self.pause()?;
+        }
+        let command = SnapshotCommand::Take {
+            snapshot_path: path.clone(),
+            compress_memory: false,
+            encrypt: false,
+        };
+        let result = match self.handle_request(&VmRequest::Snapshot(command)) {
+            Ok(VmResponse::Ok) => Ok(()),
+            Ok(VmResponse::Err(e)) if e.errno() == libc::ENOTSUP => {
+                Err(anyhow!("crosvm was built without snapshot support"))
+            }
+            e => Err(anyhow!("Error taking snapshot to {:?}: {:?}", path, e)),
+        };
+        let result = result.and_then(|()| {
+            let dest_path = PathBuf::from(format!("/proc/self/fd/{}", dest.as_raw_fd()));
+            copy_dir_contents(&path, &dest_path).context("Failed to copy snapshot")
+        });
+        // Whether or not it was complete, the snapshot has no further use here.
+        if let Err(e) = std::fs::remove_dir_all(&path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                error!("Error removing snapshot {:?}: {}", path, e);
+            }
+        }
+        if resume && !was_paused {
+            // Resume even if the snapshot failed, but report the snapshot error first.
+            let resumed = self.resume();
+            result.and(resumed)
+        } else {
+            result
+        }
+    }
+
     /// Responds to memory-trimming notifications by inflating the virtio
     /// balloon to reclaim guest memory.
     pub fn trim_memory(&self, level: MemoryTrimLevel) -> Result<(), Error> {
+        // Trim is just a hint, so we can ignore it if the balloon isn't ready yet.
+        let Some(stats) = self.balloon_stats()? else {
+            return Ok(());
+        };
+        debug!(
+            "Balloon of {} is {} bytes, {:?} bytes available of {:?}",
+            &self, stats.balloon_actual, stats.available_memory, stats.total_memory
+        );
+        if let Some(total_memory) = stats.total_memory {
+            // Reclaim up to 50% of total memory assuming worst case
+            // most memory is anonymous and must be swapped to zram
+            // with an approximate 2:1 compression ratio.
+            let pct = match level {
+                MemoryTrimLevel::TRIM_MEMORY_RUNNING_CRITICAL => 50,
+                MemoryTrimLevel::TRIM_MEMORY_RUNNING_LOW => 30,
+                MemoryTrimLevel::TRIM_MEMORY_RUNNING_MODERATE => 10,
+                _ => bail!("Invalid memory trim level {:?}", level),
+            };
+            self.set_balloon_size(total_memory * pct / 100)?;
+        }
+        Ok(())
+    }
+
+    /// Inflates or deflates the virtio balloon to the given size, so that that many bytes of
+    /// memory are reclaimed from the guest.
+    pub fn set_balloon_size(&self, num_bytes: u64) -> Result<(), Error> {
+        let command = BalloonControlCommand::Adjust { num_bytes, wait_for_success: false };
+        if let Err(e) = self.handle_request(&VmRequest::BalloonCommand(command)) {
+            bail!("Error sending balloon adjustment: {:?}", e);
+        }
+        Ok(())
+    }
+
+    /// Returns the memory statistics which the guest reports through the virtio balloon, or `None`
+    /// if the balloon protocol isn't initialized.
+    pub fn balloon_stats(&self) -> Result<Option<BalloonStats>, Error> {
         let request = VmRequest::BalloonCommand(BalloonControlCommand::Stats {});
-        match vm_control::client::handle_request(&request, &self.crosvm_control_socket_path) {
-            Ok(VmResponse::BalloonStats { stats, balloon_actual: _ }) => {
-                if let Some(total_memory) = stats.total_memory {
-                    // Reclaim up to 50% of total memory assuming worst case
-                    // most memory is anonymous and must be swapped to zram
-                    // with an approximate 2:1 compression ratio.
-                    let pct = match level {
-                        MemoryTrimLevel::TRIM_MEMORY_RUNNING_CRITICAL => 50,
-                        MemoryTrimLevel::TRIM_MEMORY_RUNNING_LOW => 30,
-                        MemoryTrimLevel::TRIM_MEMORY_RUNNING_MODERATE => 10,
-                        _ => bail!("Invalid memory trim level {:?}", level),
-                    };
-                    let command = BalloonControlCommand::Adjust {
-                        num_bytes: total_memory * pct / 100,
-                        wait_for_success: false,
-                    };
-                    if let Err(e) = vm_control::client::handle_request(
-                        &VmRequest::BalloonCommand(command),
-                        &self.crosvm_control_socket_path,
-                    ) {
-                        bail!("Error sending balloon adjustment: {:?}", e);
-                    }
-                }
-            }
+        match self.handle_request(&request) {
+            Ok(VmResponse::BalloonStats { stats, balloon_actual }) => Ok(Some(BalloonStats {
+                balloon_actual,
+                available_memory: stats.available_memory,
+                total_memory: stats.total_memory,
+            })),
             Ok(VmResponse::Err(e)) => {
                 // ENOTSUP is returned when the balloon protocol is not initialized. This
                 // can occur for numerous reasons: Guest is still booting, guest doesn't
-                // support ballooning, host doesn't support ballooning. We don't log or
-                // raise an error in this case: trim is just a hint and we can ignore it.
+                // support ballooning, host doesn't support ballooning.
                 if e.errno() != libc::ENOTSUP {
                     bail!("Errno return when requesting balloon stats: {}", e.errno())
                 }
+                Ok(None)
             }
             e => bail!("Error requesting balloon stats: {:?}", e),
         }
-        Ok(())
     }
 
     /// Checks if ramdump has been created. If so, send it to tombstoned.
@@ -720,7 +1615,7 @@ fn check_if_all_cpus_allowed() -> Result<bool> {
 }
 
 // Get guest time from /proc/[crosvm pid]/stat
-fn get_guest_time(pid: u32) -> Result<i64> {
+fn get_guest_time(pid: i32) -> Result<i64> {
     let file = read_to_string(format!("/proc/{}/stat", pid))?;
     let data_list: Vec<_> = file.split_whitespace().collect();
 
@@ -739,7 +1634,7 @@ fn get_guest_time(pid: u32) -> Result<i64> {
 }
 
 // Get rss from /proc/[crosvm pid]/smaps
-fn get_rss(pid: u32) -> Result<Rss> {
+fn get_rss(pid: i32) -> Result<Rss> {
     let file = read_to_string(format!("/proc/{}/smaps", pid))?;
     let lines: Vec<_> = file.split('\n').collect();
 
@@ -767,12 +1662,32 @@ fn get_rss(pid: u32) -> Result<Rss> {
     Ok(Rss { vm: rss_vm_total, crosvm: rss_crosvm_total })
 }
 
-fn death_reason(result: &Result<ExitStatus, io::Error>, mut failure_reason: &str) -> DeathReason {
-    if let Some((reason, info)) = failure_reason.split_once('|') {
-        // Separator indicates extra context information is present after the failure name.
-        error!("Failure info: {info}");
-        failure_reason = reason;
+/// How the crosvm process ended, as far as can be told from its exit status.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum CrosvmExit {
+    /// crosvm exited by itself, with an exit code.
+    Clean,
+    /// crosvm was terminated by any other signal, e.g. because it crashed.
+    CrashSignal(i32),
+    /// crosvm was killed with `SIGKILL`, `SIGTERM` or `SIGINT`, either by us or e.g. by the low
+    /// memory killer.
+    Killed,
+}
+
+impl From<&ExitStatus> for CrosvmExit {
+    fn from(status: &ExitStatus) -> Self {
+        match status.signal() {
+            None => CrosvmExit::Clean,
+            Some(libc::SIGKILL | libc::SIGTERM | libc::SIGINT) => CrosvmExit::Killed,
+            Some(signal) => CrosvmExit::CrashSignal(signal),
+        }
     }
+}
+
+fn death_reason(result: &Result<ExitStatus, io::Error>, failure_reason: &str) -> DeathReason {
+    // Separator indicates extra context information is present after the failure name.
+    let failure_reason =
+        failure_reason.split_once('|').map_or(failure_reason, |(reason, _)| reason);
     if let Ok(status) = result {
         match failure_reason {
             "PVM_FIRMWARE_PUBLIC_KEY_MISMATCH" => {
@@ -797,14 +1712,17 @@ fn death_reason(result: &Result<ExitStatus, io::Error>, mut failure_reason: &str
             "HANGUP" => return DeathReason::HANGUP,
             _ => {}
         }
-        match status.code() {
-            None => DeathReason::KILLED,
-            Some(0) => DeathReason::SHUTDOWN,
-            Some(CROSVM_START_ERROR_STATUS) => DeathReason::START_FAILED,
-            Some(CROSVM_REBOOT_STATUS) => DeathReason::REBOOT,
-            Some(CROSVM_CRASH_STATUS) => DeathReason::CRASH,
-            Some(CROSVM_WATCHDOG_REBOOT_STATUS) => DeathReason::WATCHDOG_REBOOT,
-            Some(_) => DeathReason::UNKNOWN,
+        match CrosvmExit::from(status) {
+            CrosvmExit::Killed => DeathReason::KILLED,
+            CrosvmExit::CrashSignal(_) => DeathReason::CRASH,
+            CrosvmExit::Clean => match status.code() {
+                Some(0) => DeathReason::SHUTDOWN,
+                Some(CROSVM_START_ERROR_STATUS) => DeathReason::START_FAILED,
+                Some(CROSVM_REBOOT_STATUS) => DeathReason::REBOOT,
+                Some(CROSVM_CRASH_STATUS) => DeathReason::CRASH,
+                Some(CROSVM_WATCHDOG_REBOOT_STATUS) => DeathReason::WATCHDOG_REBOOT,
+                _ => DeathReason::UNKNOWN,
+            },
         }
     } else {
         DeathReason::INFRASTRUCTURE_ERROR
@@ -861,16 +1779,80 @@ fn append_platform_devices(
     Ok(())
 }
 
+/// A crosvm command line built by [`CrosvmConfig::to_command`].
+pub struct CrosvmCommand {
+    /// The command to run crosvm with.
+    pub command: Command,
+    /// Files which the command refers to by FD, and so must stay open until crosvm has been
+    /// spawned.
+    _files: Vec<File>,
+}
+
 /// Starts an instance of `crosvm` to manage a new VM.
 fn run_vm(
-    config: CrosvmConfig,
+    config: &mut CrosvmConfig,
     crosvm_control_socket_path: &Path,
     failure_pipe_write: File,
 ) -> Result<SharedChild, Error> {
-    validate_config(&config)?;
+    // If there are console sinks, crosvm writes the console output to a pipe instead, which is
+    // read by a thread copying it to console_out_fd and the sinks.
+    if !config.console_out_sinks.0.is_empty() {
+        let mut sinks = mem::take(&mut config.console_out_sinks.0);
+        if let Some(console_out_fd) = config.console_out_fd.take() {
+            sinks.insert(0, Box::new(console_out_fd));
+        }
+        let (console_read, console_write) = create_pipe()?;
+        thread::spawn(move || tee_console(console_read, sinks));
+        config.console_out_fd = Some(console_write);
+    }
+
+    let control_server_socket = UnixSeqpacketListener::bind(crosvm_control_socket_path)
+        .context("failed to create control server")?;
+    let mut crosvm_command =
+        config.to_command(control_server_socket.as_raw_descriptor(), &failure_pipe_write)?;
+
+    print_crosvm_args(&crosvm_command.command);
+
+    let result = SharedChild::spawn(&mut crosvm_command.command).with_context(|| {
+        format!("Failed to spawn {CROSVM_PATH}, is the com.android.virt APEX mounted?")
+    })?;
+    debug!("Spawned crosvm({}).", result.id());
+    Ok(result)
+}
+
+impl CrosvmConfig {
+    /// Builds the command to run crosvm with for this config, without running it.
+    ///
+    /// `control_socket` is the listening socket for the crosvm control socket, and
+    /// `failure_pipe_write` the pipe which the VM reports the reason of a failure to.
+    pub fn to_command(
+        &self,
+        control_socket: RawFd,
+        failure_pipe_write: &File,
+    ) -> Result<CrosvmCommand, Error> {
+        build_command(self, control_socket, failure_pipe_write)
+    }
+
+    /// Replaces the initrd by a copy with the bootconfig appended to it, if there is one, and
+    /// tells the kernel to read it.
+    fn append_bootconfig_to_initrd(&mut self) -> Result<(), Error> {
+        if let (Some(initrd), Some(bootconfig)) = (&self.initrd, &self.bootconfig) {
+            self.initrd = Some(append_bootconfig(initrd, bootconfig)?);
+            self.bootconfig = None;
+            self.params.flag("bootconfig");
+        }
+        Ok(())
+    }
+}
+
+fn build_command(
+    config: &CrosvmConfig,
+    control_socket: RawFd,
+    failure_pipe_write: &File,
+) -> Result<CrosvmCommand, Error> {
+    validate_config(config)?;
 
     let mut command = Command::new(CROSVM_PATH);
-    // TODO(qwandor): Remove --disable-sandbox.
     command
         .arg("--extended-status")
         // Configure the logger for the crosvm process to silence logs from the disk crate which
@@ -878,17 +1860,25 @@ fn run_vm(
         .arg("--log-level")
         .arg("info,disk=warn")
         .arg("run")
-        .arg("--disable-sandbox")
         .arg("--cid")
         .arg(config.cid.to_string());
 
+    match &config.sandbox {
+        SandboxConfig::Disabled => command.arg("--disable-sandbox"),
+        SandboxConfig::Enabled { policy_dir } => {
+            command.arg("--seccomp-policy-dir").arg(policy_dir)
+        }
+    };
+
     if system_properties::read_bool("hypervisor.memory_reclaim.supported", false)? {
         command.arg("--balloon-page-reporting");
-    } else {
+    } else if !config.balloon {
         command.arg("--no-balloon");
     }
 
     let mut memory_mib = config.memory_mib;
+    // The parameters added here come first, so that those of the config take precedence.
+    let mut params = KernelParams::new();
 
     if config.protected {
         match system_properties::read(SYSPROP_CUSTOM_PVMFW_PATH)? {
@@ -898,11 +1888,13 @@ fn run_vm(
             _ => command.arg("--protected-vm"),
         };
 
-        // 3 virtio-console devices + vsock = 4.
-        let virtio_pci_device_count = 4 + config.disks.len();
-        // crosvm virtio queue has 256 entries, so 2 MiB per device (2 pages per entry) should be
-        // enough.
-        let swiotlb_size_mib = 2 * virtio_pci_device_count as u32;
+        let swiotlb_size_mib = config.swiotlb_mib.map(NonZeroU32::get).unwrap_or_else(|| {
+            // 3 virtio-console devices + vsock = 4.
+            let virtio_pci_device_count = 4 + config.disks.len();
+            // crosvm virtio queue has 256 entries, so 2 MiB per device (2 pages per entry) should
+            // be enough.
+            2 * virtio_pci_device_count as u32
+        });
         command.arg("--swiotlb").arg(swiotlb_size_mib.to_string());
 
         // b/346770542 for consistent "usable" memory across protected and non-protected VMs under
@@ -919,27 +1911,32 @@ fn run_vm(
             // Protected VM needs to reserve memory for ramdump here. Note that we reserve more
             // memory for the restricted dma pool.
             let ramdump_reserve = RAMDUMP_RESERVED_MIB + swiotlb_size_mib;
-            command.arg("--params").arg(format!("crashkernel={ramdump_reserve}M"));
+            params.set("crashkernel", &format!("{ramdump_reserve}M"))?;
         }
     } else if config.ramdump.is_some() {
-        command.arg("--params").arg(format!("crashkernel={RAMDUMP_RESERVED_MIB}M"));
+        params.set("crashkernel", &format!("{RAMDUMP_RESERVED_MIB}M"))?;
     }
     if config.debug_config.debug_level == DebugLevel::NONE
         && config.debug_config.should_prepare_console_output()
     {
         // bootconfig.normal will be used, but we need log.
-        command.arg("--params").arg("printk.devkmsg=on");
-        command.arg("--params").arg("console=hvc0");
+        params.set("printk.devkmsg", "on")?.set("console", "hvc0")?;
     }
 
     if let Some(memory_mib) = memory_mib {
         command.arg("--mem").arg(memory_mib.to_string());
     }
 
-    if let Some(cpus) = config.cpus {
+    if let Some(cpu_topology) = config.cpu_topology {
+        command.arg("--cpus").arg(cpu_topology.to_crosvm_arg());
+    } else if let Some(cpus) = config.cpus {
         command.arg("--cpus").arg(cpus.to_string());
     }
 
+    if let Some(cpu_affinity) = &config.cpu_affinity {
+        command.arg("--cpu-affinity").arg(cpu_affinity);
+    }
+
     if config.host_cpu_topology {
         if cfg!(virt_cpufreq) && check_if_all_cpus_allowed()? {
             command.arg("--host-cpu-topology");
@@ -971,20 +1968,30 @@ fn run_vm(
     //
     // When [console|log]_fd is not specified, the devices are attached to sink, which means what's
     // written there is discarded.
-    let console_out_arg = format_serial_out_arg(&mut preserved_fds, &config.console_out_fd);
+    let console_out_arg = match &config.console_target {
+        Some(console_target) => console_target.to_serial_arg(&mut preserved_fds),
+        None => format_serial_out_arg(&mut preserved_fds, &config.console_out_fd),
+    };
     let console_in_arg = config
         .console_in_fd
         .as_ref()
         .map(|fd| format!(",input={}", add_preserved_fd(&mut preserved_fds, fd)))
         .unwrap_or_default();
     let log_arg = format_serial_out_arg(&mut preserved_fds, &config.log_fd);
-    let failure_serial_path = add_preserved_fd(&mut preserved_fds, &failure_pipe_write);
+    let failure_serial_path = add_preserved_fd(&mut preserved_fds, failure_pipe_write);
     let ramdump_arg = format_serial_out_arg(&mut preserved_fds, &config.ramdump);
     let console_input_device = config.console_input_device.as_deref().unwrap_or(CONSOLE_HVC0);
     match console_input_device {
         CONSOLE_HVC0 | CONSOLE_TTYS0 => {}
         _ => bail!("Unsupported serial device {console_input_device}"),
     };
+    // Only one device can listen on a Unix socket, so attach it to the console input device only.
+    let console_out_arg_for = |device: &str| match &config.console_target {
+        Some(ConsoleTarget::UnixSocket(_)) if device != console_input_device => {
+            "type=sink".to_owned()
+        }
+        _ => console_out_arg.clone(),
+    };
 
     // Warning: Adding more serial devices requires you to shift the PCI device ID of the boot
     // disks in bootconfig.x86_64. This is because x86 crosvm puts serial devices and the block
@@ -993,7 +2000,7 @@ fn run_vm(
     // /dev/ttyS0
     command.arg(format!(
         "--serial={}{},hardware=serial,num=1",
-        &console_out_arg,
+        console_out_arg_for(CONSOLE_TTYS0),
         if console_input_device == CONSOLE_TTYS0 { &console_in_arg } else { "" }
     ));
     // /dev/ttyS1
@@ -1001,7 +2008,7 @@ fn run_vm(
     // /dev/hvc0
     command.arg(format!(
         "--serial={}{},hardware=virtio-console,num=1",
-        &console_out_arg,
+        console_out_arg_for(CONSOLE_HVC0),
         if console_input_device == CONSOLE_HVC0 { &console_in_arg } else { "" }
     ));
     // /dev/hvc1
@@ -1013,34 +2020,69 @@ fn run_vm(
         command.arg("--bios").arg(add_preserved_fd(&mut preserved_fds, bootloader));
     }
 
+    // Files which the command refers to by FD and must stay open until crosvm has been spawned.
+    let mut files = vec![];
+
     if let Some(initrd) = &config.initrd {
         command.arg("--initrd").arg(add_preserved_fd(&mut preserved_fds, initrd));
     }
 
-    if let Some(params) = &config.params {
-        command.arg("--params").arg(params);
+    params.extend(&config.params);
+    if !params.is_empty() {
+        command.arg("--params").arg(params.to_string());
     }
 
     for disk in &config.disks {
-        command
-            .arg(if disk.writable { "--rwdisk" } else { "--disk" })
-            .arg(add_preserved_fd(&mut preserved_fds, &disk.image));
+        let mut path = if disk.cache == CacheMode::WritesThrough {
+            // crosvm has no option for this, so give it an open file description with O_DSYNC set.
+            let file = File::options()
+                .read(true)
+                .write(disk.writable)
+                .custom_flags(libc::O_DSYNC)
+                .open(format!("/proc/self/fd/{}", disk.image.as_raw_fd()))
+                .context("Failed to reopen disk image with O_DSYNC")?;
+            let path = add_preserved_fd(&mut preserved_fds, &file);
+            files.push(file);
+            path
+        } else {
+            add_preserved_fd(&mut preserved_fds, &disk.image)
+        };
+        if disk.cache == CacheMode::None {
+            path.push_str(",direct=true");
+        }
+        if disk.block_async {
+            path.push_str(",async-executor=uring");
+        }
+        command.arg(if disk.writable { "--rwdisk" } else { "--disk" }).arg(path);
+    }
+
+    for dir in &config.shared_dirs {
+        let path = add_preserved_fd(&mut preserved_fds, &dir.directory);
+        command.arg("--shared-dir").arg(format!("{path}:{}:type=fs", dir.tag));
+    }
+
+    for pmem_file in &config.pmem_files {
+        command.arg("--pmem-device").arg(add_preserved_fd(&mut preserved_fds, pmem_file));
     }
 
     if let Some(kernel) = &config.kernel {
         command.arg(add_preserved_fd(&mut preserved_fds, kernel));
     }
 
-    let control_server_socket = UnixSeqpacketListener::bind(crosvm_control_socket_path)
-        .context("failed to create control server")?;
-    command
-        .arg("--socket")
-        .arg(add_preserved_fd(&mut preserved_fds, &control_server_socket.as_raw_descriptor()));
+    if let Some(restore_from_dir) = &config.restore_from_dir {
+        command.arg("--restore").arg(add_preserved_fd(&mut preserved_fds, restore_from_dir));
+    }
+
+    command.arg("--socket").arg(add_preserved_fd(&mut preserved_fds, &control_socket));
 
     if let Some(dt_overlay) = &config.device_tree_overlay {
         command.arg("--device-tree-overlay").arg(add_preserved_fd(&mut preserved_fds, dt_overlay));
     }
 
+    for dt_overlay in &config.device_tree_overlays {
+        command.arg("--device-tree-overlay").arg(add_preserved_fd(&mut preserved_fds, dt_overlay));
+    }
+
     if cfg!(paravirtualized_devices) {
         if let Some(gpu_config) = &config.gpu_config {
             let mut gpu_args = Vec::new();
@@ -1100,7 +2142,11 @@ fn run_vm(
         if let Some(tap) = &config.tap {
             let tap_fd = tap.as_raw_fd();
             preserved_fds.push(tap_fd);
-            command.arg("--net").arg(format!("tap-fd={}", tap_fd));
+            let mac_arg = config.tap_mac_address.map_or_else(String::new, |mac| {
+                let mac = mac.map(|b| format!("{b:02x}")).join(":");
+                format!(",mac={mac}")
+            });
+            command.arg("--net").arg(format!("tap-fd={}{}", tap_fd, mac_arg));
         }
     }
 
@@ -1136,7 +2182,7 @@ fn run_vm(
         command.arg("--boost-uclamp");
     }
 
-    append_platform_devices(&mut command, &mut preserved_fds, &config)?;
+    append_platform_devices(&mut command, &mut preserved_fds, config)?;
 
     debug!("Preserving FDs {:?}", preserved_fds);
     command.preserved_fds(preserved_fds);
@@ -1147,21 +2193,192 @@ fn run_vm(
         }
     }
 
-    print_crosvm_args(&command);
+    // The nice value is per thread, so set it before exec rather than on the PID after spawning,
+    // so that all threads of crosvm inherit it.
+    let nice = config.scheduler_priority;
+    let ioprio = config.io_priority_class.map(IoPriorityClass::ioprio);
+    if nice.is_some() || ioprio.is_some() {
+        // SAFETY: The closure only makes system calls which are async-signal-safe, and doesn't
+        // allocate.
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(nice) = nice {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if let Some(ioprio) = ioprio {
+                    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+                    if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
 
-    let result = SharedChild::spawn(&mut command)?;
-    debug!("Spawned crosvm({}).", result.id());
-    Ok(result)
+    Ok(CrosvmCommand { command, _files: files })
 }
 
 /// Ensure that the configuration has a valid combination of fields set, or return an error if not.
 fn validate_config(config: &CrosvmConfig) -> Result<(), Error> {
-    if config.bootloader.is_none() && config.kernel.is_none() {
-        bail!("VM must have either a bootloader or a kernel image.");
+    if config.bootloader.is_none() && config.kernel.is_none() && config.restore_from_dir.is_none() {
+        bail!("VM must have either a bootloader, a kernel image or a snapshot to restore from.");
+    }
+    if let Some(restore_from_dir) = &config.restore_from_dir {
+        if !restore_from_dir.metadata().context("Failed to stat snapshot")?.is_dir() {
+            bail!("Snapshot to restore from must be a directory.");
+        }
     }
     if config.bootloader.is_some() && (config.kernel.is_some() || config.initrd.is_some()) {
         bail!("Can't have both bootloader and kernel/initrd image.");
     }
+    if config.bootconfig.is_some() && config.initrd.is_none() {
+        bail!("Can't have bootconfig without an initrd image.");
+    }
+    for disk in &config.disks {
+        if disk.format == DiskFormat::Qcow2 {
+            let mut magic = [0; QCOW2_MAGIC.len()];
+            disk.image.read_exact_at(&mut magic, 0).context("Failed to read disk image header")?;
+            if &magic != QCOW2_MAGIC {
+                bail!("Disk image is not in qcow2 format.");
+            }
+        }
+    }
+    for dt_overlay in &config.device_tree_overlays {
+        let mut magic = [0; 4];
+        dt_overlay.read_exact_at(&mut magic, 0).context("Failed to read device tree overlay")?;
+        if u32::from_be_bytes(magic) != FDT_MAGIC {
+            bail!("Device tree overlay is not a flattened device tree blob.");
+        }
+    }
+    if !config.pmem_files.is_empty() {
+        // SAFETY: Only integer constants passed back and forth.
+        let page_size = unsafe { sysconf(_SC_PAGESIZE) } as u64;
+        for pmem_file in &config.pmem_files {
+            let size = pmem_file.metadata().context("Failed to get size of pmem file")?.len();
+            if size % page_size != 0 {
+                bail!("Size {size} of pmem file isn't a multiple of the page size {page_size}.");
+            }
+        }
+    }
+    for dir in &config.shared_dirs {
+        if dir.tag.is_empty() || dir.tag.contains(':') {
+            bail!("Invalid tag {:?} for shared directory.", dir.tag);
+        }
+        let metadata =
+            dir.directory.metadata().context("Failed to get shared directory metadata")?;
+        if !metadata.is_dir() {
+            bail!("Shared directory {:?} isn't a directory.", dir.tag);
+        }
+        // Unlike disks, virtio-fs in crosvm has no read-only mode.
+        if !dir.writable {
+            bail!("Shared directory {:?} can't be read-only.", dir.tag);
+        }
+    }
+    if let Some(memory_mib) = config.memory_mib {
+        let default_min_memory_mib = if config.protected {
+            DEFAULT_MIN_PROTECTED_MEMORY_MIB
+        } else {
+            DEFAULT_MIN_MEMORY_MIB
+        };
+        let min_memory_mib = config.min_memory_mib.map_or(default_min_memory_mib, NonZeroU32::get);
+        if memory_mib.get() < min_memory_mib {
+            bail!("VM memory of {memory_mib} MiB is below the minimum of {min_memory_mib} MiB.");
+        }
+        let host_memory_mib = get_host_memory_mib()?;
+        if u64::from(memory_mib.get()) > host_memory_mib {
+            bail!("VM memory of {memory_mib} MiB exceeds host memory of {host_memory_mib} MiB.");
+        }
+    }
+    if let Some(swiotlb_mib) = config.swiotlb_mib {
+        if !config.protected {
+            bail!("swiotlb size can only be set for protected VMs.");
+        }
+        if let Some(memory_mib) = config.memory_mib {
+            if swiotlb_mib > memory_mib {
+                bail!("swiotlb size of {swiotlb_mib} MiB exceeds VM memory of {memory_mib} MiB.");
+            }
+        } else {
+            let host_memory_mib = get_host_memory_mib()?;
+            if u64::from(swiotlb_mib.get()) > host_memory_mib {
+                bail!(
+                    "swiotlb size of {} MiB exceeds host memory of {} MiB.",
+                    swiotlb_mib,
+                    host_memory_mib
+                );
+            }
+        }
+    }
+    if let Some(console_target) = &config.console_target {
+        if config.console_out_fd.is_some() || config.console_in_fd.is_some() {
+            bail!("Can't have both a console target and console file descriptors.");
+        }
+        if let ConsoleTarget::UnixSocket(path) = console_target {
+            if path.to_str().map_or(true, |path| path.contains(',')) {
+                bail!("Invalid console socket path {path:?}.");
+            }
+        }
+    }
+    if let SandboxConfig::Enabled { policy_dir } = &config.sandbox {
+        if !policy_dir.is_dir() {
+            bail!("Seccomp policy directory {policy_dir:?} doesn't exist or isn't a directory.");
+        }
+    }
+    if let Some(nice) = config.scheduler_priority {
+        if !(-20..=19).contains(&nice) {
+            bail!("Invalid scheduler priority {nice}, must be in the range -20 to 19.");
+        }
+    }
+    if let Some(IoPriorityClass::BestEffort(level)) = config.io_priority_class {
+        if level > 7 {
+            bail!("Invalid best-effort I/O priority level {level}, must be in the range 0 to 7.");
+        }
+    }
+    if config.protected && (config.gpu_config.is_some() || config.display_config.is_some()) {
+        bail!("Protected VMs don't support GPU or display devices.");
+    }
+    if config.protected && config.gdb_port.is_some() {
+        bail!("Can't use gdb with protected VMs.");
+    }
+    if config.boot_timeout.is_some_and(|timeout| timeout.is_zero()) {
+        bail!("Boot timeout must not be zero.");
+    }
+    if let Some(cpus) = config.cpus {
+        let host_cpus = get_num_cpus().context("Could not determine the number of host CPUs")?;
+        if cpus.get() as usize > host_cpus {
+            bail!("VM can't have {cpus} CPUs, the host only has {host_cpus}.");
+        }
+    }
+    if let Some(cpu_topology) = config.cpu_topology {
+        if config.host_cpu_topology {
+            bail!("Can't set a CPU topology when using the host CPU topology.");
+        }
+        let num_cpus = cpu_topology
+            .sockets
+            .checked_mul(cpu_topology.cores_per_socket)
+            .and_then(|n| n.checked_mul(cpu_topology.threads_per_core))
+            .with_context(|| format!("CPU topology {cpu_topology:?} has too many vCPUs"))?
+            .get();
+        let cpus = config.cpus.map_or(0, NonZeroU32::get);
+        if cpus != num_cpus {
+            bail!("CPU topology {cpu_topology:?} has {num_cpus} vCPUs, but the VM has {cpus}.");
+        }
+    }
+    if let Some(cpu_affinity) = &config.cpu_affinity {
+        let (vcpus, host_cpus) = parse_cpu_affinity(cpu_affinity)?;
+        let num_host_cpus =
+            get_num_cpus().context("Could not determine the number of host CPUs")?;
+        if let Some(host_cpu) = host_cpus.iter().find(|&&cpu| cpu >= num_host_cpus) {
+            bail!("CPU affinity refers to host CPU {host_cpu}, the host only has {num_host_cpus}.");
+        }
+        if let Some(cpus) = config.cpus {
+            if let Some(vcpu) = vcpus.iter().find(|&&vcpu| vcpu >= cpus.get() as usize) {
+                bail!("CPU affinity refers to vCPU {vcpu}, the VM only has {cpus}.");
+            }
+        }
+    }
     let version = Version::parse(CROSVM_PLATFORM_VERSION).unwrap();
     if !config.platform_version.matches(&version) {
         bail!(
@@ -1175,6 +2392,53 @@ fn validate_config(config: &CrosvmConfig) -> Result<(), Error> {
     Ok(())
 }
 
+/// Returns the total amount of physical memory of the host, in MiB.
+fn get_host_memory_mib() -> Result<u64, Error> {
+    // SAFETY: Only integer constants passed back and forth.
+    let (pages, page_size) = unsafe { (sysconf(_SC_PHYS_PAGES), sysconf(_SC_PAGESIZE)) };
+    if pages <= 0 || page_size <= 0 {
+        bail!("Could not determine the amount of host memory.");
+    }
+    Ok(pages as u64 * page_size as u64 / (1024 * 1024))
+}
+
+/// Parses a value for crosvm's `--cpu-affinity` flag. Returns the vCPU indices explicitly mentioned
+/// in it, and all the host CPUs it refers to.
+fn parse_cpu_affinity(cpu_affinity: &str) -> Result<(Vec<usize>, Vec<usize>), Error> {
+    if !cpu_affinity.contains('=') {
+        // A single set of host CPUs which all vCPUs may run on.
+        return Ok((vec![], parse_cpu_set(cpu_affinity)?));
+    }
+    let mut vcpus = vec![];
+    let mut host_cpus = vec![];
+    for mapping in cpu_affinity.split(':') {
+        let Some((vcpu, cpu_set)) = mapping.split_once('=') else {
+            bail!("Invalid vCPU affinity {mapping:?}, expected VCPU=CPUSET");
+        };
+        vcpus.push(vcpu.parse().with_context(|| format!("Invalid vCPU index {vcpu:?}"))?);
+        host_cpus.extend(parse_cpu_set(cpu_set)?);
+    }
+    Ok((vcpus, host_cpus))
+}
+
+/// Parses a comma-separated list of CPUs or CPU ranges, e.g. `0,2-4`.
+fn parse_cpu_set(cpu_set: &str) -> Result<Vec<usize>, Error> {
+    let mut cpus = vec![];
+    for part in cpu_set.split(',') {
+        if let Some((first, last)) = part.split_once('-') {
+            let first: usize = first.parse().with_context(|| format!("Invalid CPU {first:?}"))?;
+            let last: usize = last.parse().with_context(|| format!("Invalid CPU {last:?}"))?;
+            if first > last {
+                bail!("Invalid CPU range {part:?}");
+            }
+            cpus.extend(first..=last);
+        } else {
+            cpus.push(part.parse().with_context(|| format!("Invalid CPU {part:?}"))?);
+        }
+    }
+    Ok(cpus)
+}
+
 /// Print arguments of the crosvm command. In doing so, /proc/self/fd/XX is annotated with the
 /// actual file path if the FD is backed by a regular file. If not, the /proc path is printed
 /// unmodified.
@@ -1218,8 +2482,474 @@ fn format_serial_out_arg(preserved_fds: &mut Vec<RawFd>, file: &Option<File>) ->
     }
 }
 
+/// Copies the console output of a VM from `console` to each of `sinks` until `console` is closed,
+/// so that it can be written to several places at once. A sink which fails to be written to is
+/// dropped, without affecting the others.
+fn tee_console(mut console: File, mut sinks: Vec<Box<dyn Write + Send>>) {
+    let mut buf = [0; 4096];
+    loop {
+        let n = match console.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                warn!("Error reading VM console output: {e}");
+                break;
+            }
+        };
+        sinks.retain_mut(|sink| match sink.write_all(&buf[..n]) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Error writing VM console output, dropping sink: {e}");
+                false
+            }
+        });
+    }
+}
+
+/// Sets both the read and write timeouts of `stream`, i.e. `SO_RCVTIMEO` and `SO_SNDTIMEO`. `None`
+/// clears them, so that operations on the stream block indefinitely.
+pub fn set_stream_timeouts(stream: &VsockStream, timeout: Option<Duration>) -> Result<()> {
+    stream.set_read_timeout(timeout).context("Failed to set read timeout")?;
+    stream.set_write_timeout(timeout).context("Failed to set write timeout")?;
+    Ok(())
+}
+
+/// Copies the files in the directory `from`, and recursively its subdirectories, into the existing
+/// directory `to`.
+fn copy_dir_contents(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            std::fs::create_dir(&dest)?;
+            copy_dir_contents(&entry.path(), &dest)?;
+        } else {
+            std::fs::copy(entry.path(), dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the log output of a VM from `log` line by line until it is closed, writing it to `log_fd`
+/// if any and calling `callback` with each line, without its line terminator.
+fn read_log_lines(log: impl Read, mut log_fd: Option<impl Write>, callback: impl Fn(&str)) {
+    let mut log = BufReader::new(log);
+    let mut line = vec![];
+    loop {
+        line.clear();
+        match log.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => {
+                warn!("Error reading VM log output: {e}");
+                break;
+            }
+        }
+        if let Some(file) = &mut log_fd {
+            if let Err(e) = file.write_all(&line) {
+                warn!("Error writing VM log output, dropping log_fd: {e}");
+                log_fd = None;
+            }
+        }
+        callback(String::from_utf8_lossy(&line).trim_end_matches(['\n', '\r']));
+    }
+}
+
+/// Returns the payload state which a line of the log output of Microdroid shows the payload to
+/// have reached, if any.
+fn payload_milestone(line: &str) -> Option<PayloadState> {
+    PAYLOAD_LOG_MILESTONES
+        .iter()
+        .find(|(message, _)| line.contains("microdroid_manager") && line.ends_with(message))
+        .map(|&(_, state)| state)
+}
+
+/// Returns a copy of `initrd` with `bootconfig` appended to it, followed by the trailer which the
+/// kernel uses to find it.
+fn append_bootconfig(initrd: &File, bootconfig: &File) -> Result<File, Error> {
+    // SAFETY: The name is a valid C string, and the return value is checked before it is used.
+    let fd = unsafe { libc::memfd_create(cstr!("initrd").as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error()).context("Failed to create memfd for initrd");
+    }
+    // SAFETY: The file descriptor was just created, so nothing else owns it.
+    let mut combined = unsafe { File::from_raw_fd(fd) };
+    let mut initrd = initrd;
+    initrd.seek(SeekFrom::Start(0))?;
+    let initrd_size = io::copy(&mut initrd, &mut combined).context("Failed to copy initrd")?;
+
+    let mut bootconfig_data = vec![];
+    let mut bootconfig = bootconfig;
+    bootconfig.seek(SeekFrom::Start(0))?;
+    bootconfig.read_to_end(&mut bootconfig_data).context("Failed to read bootconfig")?;
+    // The kernel expects the bootconfig to be null-terminated, and the end of the trailer, i.e. of
+    // the whole initrd, to be 4-byte aligned, so the bootconfig is terminated and then padded with
+    // null bytes like the kernel's tools/bootconfig does.
+    bootconfig_data.push(0);
+    let initrd_size = usize::try_from(initrd_size).context("initrd is too large")?;
+    let unpadded_size = initrd_size + bootconfig_data.len() + BOOTCONFIG_TRAILER_SIZE;
+    let padding = unpadded_size.next_multiple_of(4) - unpadded_size;
+    bootconfig_data.resize(bootconfig_data.len() + padding, 0);
+    let size: u32 = bootconfig_data.len().try_into().context("bootconfig is too large")?;
+    let checksum = bootconfig_data.iter().fold(0u32, |sum, &b| sum.wrapping_add(b.into()));
+
+    combined.write_all(&bootconfig_data)?;
+    combined.write_all(&size.to_le_bytes())?;
+    combined.write_all(&checksum.to_le_bytes())?;
+    combined.write_all(BOOTCONFIG_MAGIC)?;
+    Ok(combined)
+}
+
 /// Creates a new pipe with the `O_CLOEXEC` flag set, and returns the read side and write side.
 fn create_pipe() -> Result<(File, File), Error> {
     let (read_fd, write_fd) = pipe2(OFlag::O_CLOEXEC)?;
     Ok((read_fd.into(), write_fd.into()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use android_system_virtualizationservice_internal::aidl::android::system::virtualizationservice_internal::IGlobalVmContext::BnGlobalVmContext;
+    use binder::{BinderFeatures, Interface};
+    use std::os::unix::net::UnixListener;
+
+    struct TestGlobalVmContext;
+
+    impl Interface for TestGlobalVmContext {}
+
+    impl IGlobalVmContext for TestGlobalVmContext {
+        fn getCid(&self) -> binder::Result<i32> {
+            Ok(3)
+        }
+
+        fn getTemporaryDirectory(&self) -> binder::Result<String> {
+            Ok("/".to_owned())
+        }
+    }
+
+    fn test_context(temporary_directory: &Path) -> Result<VmContext> {
+        let global_context =
+            BnGlobalVmContext::new_binder(TestGlobalVmContext, BinderFeatures::default());
+        let listener = UnixListener::bind(temporary_directory.join("vm_service.sock"))?;
+        let vm_server = RpcServer::new_bound_socket(global_context.as_binder(), listener.into())?;
+        Ok(VmContext::new(global_context, vm_server))
+    }
+
+    fn test_config() -> Result<CrosvmConfig> {
+        Ok(CrosvmConfig {
+            cid: 3,
+            name: "test".to_owned(),
+            bootloader: None,
+            kernel: Some(File::open("/dev/null")?),
+            initrd: None,
+            bootconfig: None,
+            restore_from_dir: None,
+            disks: vec![],
+            shared_dirs: vec![],
+            pmem_files: vec![],
+            params: KernelParams::from("foo=1"),
+            protected: false,
+            debug_config: DebugConfig::new_with_debug_level(DebugLevel::FULL),
+            memory_mib: None,
+            min_memory_mib: None,
+            swiotlb_mib: None,
+            balloon: false,
+            cpus: None,
+            cpu_topology: None,
+            cpu_affinity: None,
+            host_cpu_topology: false,
+            console_out_fd: None,
+            console_out_sinks: Default::default(),
+            console_target: None,
+            console_in_fd: None,
+            log_fd: None,
+            watch_payload_log: false,
+            ramdump: None,
+            indirect_files: vec![],
+            platform_version: VersionReq::parse(CROSVM_PLATFORM_VERSION)?,
+            boot_timeout: None,
+            sandbox: SandboxConfig::Disabled,
+            gdb_port: None,
+            vfio_devices: vec![],
+            dtbo: None,
+            device_tree_overlay: None,
+            device_tree_overlays: vec![],
+            display_config: None,
+            input_device_options: vec![],
+            hugepages: false,
+            tap: None,
+            tap_mac_address: None,
+            virtio_snd_backend: None,
+            console_input_device: None,
+            boost_uclamp: false,
+            scheduler_priority: None,
+            io_priority_class: None,
+            gpu_config: None,
+        })
+    }
+
+    fn command_args(config: &CrosvmConfig) -> Result<Vec<String>> {
+        let (control_socket, failure_pipe_write) = create_pipe()?;
+        let command = config.to_command(control_socket.as_raw_fd(), &failure_pipe_write)?.command;
+        Ok(command.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect())
+    }
+
+    #[test]
+    fn to_command_protected_vm() -> Result<()> {
+        let mut config = test_config()?;
+        let args = command_args(&config)?;
+        assert!(!args.iter().any(|arg| arg.starts_with("--protected-vm")));
+
+        config.protected = true;
+        let args = command_args(&config)?;
+        assert!(args.iter().any(|arg| arg.starts_with("--protected-vm")));
+        Ok(())
+    }
+
+    #[test]
+    fn to_command_params() -> Result<()> {
+        let args = command_args(&test_config()?)?;
+        assert!(args.windows(2).any(|w| w == ["--params", "foo=1"]));
+        Ok(())
+    }
+
+    #[test]
+    fn to_command_params_of_the_config_come_last() -> Result<()> {
+        let mut config = test_config()?;
+        config.ramdump = Some(File::open("/dev/null")?);
+        config.params = KernelParams::from("foo=1 crashkernel=16M");
+        let args = command_args(&config)?;
+        assert_eq!(args.iter().filter(|arg| *arg == "--params").count(), 1);
+        assert!(args
+            .windows(2)
+            .any(|w| w == ["--params", "crashkernel=17M foo=1 crashkernel=16M"]));
+        Ok(())
+    }
+
+    #[test]
+    fn to_command_cpu_topology() -> Result<()> {
+        let mut config = test_config()?;
+        config.cpus = NonZeroU32::new(8);
+        let two = NonZeroU32::new(2).unwrap();
+        config.cpu_topology =
+            Some(CpuTopology { sockets: two, cores_per_socket: two, threads_per_core: two });
+        let args = command_args(&config)?;
+        assert!(args
+            .windows(2)
+            .any(|w| w == ["--cpus", "num-cores=8,clusters=[[0,1,2,3],[4,5,6,7]]"]));
+        Ok(())
+    }
+
+    #[test]
+    fn to_command_console_socket() -> Result<()> {
+        let mut config = test_config()?;
+        config.console_target = Some(ConsoleTarget::UnixSocket("/console.sock".into()));
+        let args = command_args(&config)?;
+        assert!(args.contains(&"--serial=type=sink,hardware=serial,num=1".to_owned()));
+        assert!(args.contains(
+            &"--serial=type=unix-stream,path=/console.sock,hardware=virtio-console,num=1"
+                .to_owned()
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn bootconfig_is_appended_to_initrd() -> Result<()> {
+        let mut initrd = tempfile::tempfile()?;
+        initrd.write_all(b"initrd")?;
+        let mut bootconfig = tempfile::tempfile()?;
+        bootconfig.write_all(b"a=b\n")?;
+
+        let mut combined = append_bootconfig(&initrd, &bootconfig)?;
+        let mut data = vec![];
+        combined.seek(SeekFrom::Start(0))?;
+        combined.read_to_end(&mut data)?;
+
+        let checksum = u32::from(b'a') + u32::from(b'=') + u32::from(b'b') + u32::from(b'\n');
+        // The null terminator and padding align the end of the 6 bytes of initrd, 4 bytes of
+        // bootconfig and the 20 bytes of trailer.
+        let expected = [
+            &b"initrd"[..],
+            b"a=b\n\0\0",
+            &6u32.to_le_bytes(),
+            &checksum.to_le_bytes(),
+            BOOTCONFIG_MAGIC,
+        ]
+        .concat();
+        assert_eq!(data, expected);
+        assert_eq!(data.len() % 4, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn bootconfig_is_null_terminated_even_if_already_aligned() -> Result<()> {
+        let mut initrd = tempfile::tempfile()?;
+        initrd.write_all(b"initrd")?;
+        let mut bootconfig = tempfile::tempfile()?;
+        bootconfig.write_all(b"a=bcd\n")?;
+
+        let mut combined = append_bootconfig(&initrd, &bootconfig)?;
+        let mut data = vec![];
+        combined.seek(SeekFrom::Start(0))?;
+        combined.read_to_end(&mut data)?;
+
+        // Without a terminator, the 6 bytes of initrd, 6 bytes of bootconfig and 20 bytes of
+        // trailer would be aligned already.
+        let bootconfig_data = b"a=bcd\n\0\0\0\0";
+        let checksum = bootconfig_data.iter().map(|&b| u32::from(b)).sum::<u32>();
+        let expected = [
+            &b"initrd"[..],
+            bootconfig_data,
+            &10u32.to_le_bytes(),
+            &checksum.to_le_bytes(),
+            BOOTCONFIG_MAGIC,
+        ]
+        .concat();
+        assert_eq!(data, expected);
+        assert_eq!(data.len() % 4, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn to_command_sandbox() -> Result<()> {
+        let mut config = test_config()?;
+        let args = command_args(&config)?;
+        assert!(args.iter().any(|arg| arg == "--disable-sandbox"));
+
+        // The policy directory has to exist.
+        config.sandbox = SandboxConfig::Enabled { policy_dir: "/".into() };
+        let args = command_args(&config)?;
+        assert!(!args.iter().any(|arg| arg == "--disable-sandbox"));
+        assert!(args.windows(2).any(|w| w == ["--seccomp-policy-dir", "/"]));
+        Ok(())
+    }
+
+    #[test]
+    fn validate_min_memory() -> Result<()> {
+        let mut config = test_config()?;
+        config.memory_mib = NonZeroU32::new(64);
+        assert!(validate_config(&config).is_ok());
+
+        config.protected = true;
+        assert!(validate_config(&config).is_err());
+
+        config.min_memory_mib = NonZeroU32::new(32);
+        assert!(validate_config(&config).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_read_only_shared_dir() -> Result<()> {
+        let mut config = test_config()?;
+        config.shared_dirs =
+            vec![SharedDir { directory: File::open("/")?, tag: "root".into(), writable: true }];
+        assert!(validate_config(&config).is_ok());
+
+        config.shared_dirs[0].writable = false;
+        assert!(validate_config(&config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_upper_bounds() -> Result<()> {
+        let mut config = test_config()?;
+        config.memory_mib = NonZeroU32::new(u32::MAX);
+        assert!(validate_config(&config).is_err());
+
+        config = test_config()?;
+        let max = NonZeroU32::MAX;
+        config.cpus = NonZeroU32::new(1);
+        config.cpu_topology =
+            Some(CpuTopology { sockets: max, cores_per_socket: max, threads_per_core: max });
+        assert!(validate_config(&config).is_err());
+
+        config = test_config()?;
+        config.boot_timeout = Some(Duration::ZERO);
+        assert!(validate_config(&config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn death_reason_from_exit_status() {
+        let exit = |code: i32| Ok(ExitStatus::from_raw(code << 8));
+        let signal = |signal: i32| Ok(ExitStatus::from_raw(signal));
+        assert_eq!(death_reason(&exit(0), ""), DeathReason::SHUTDOWN);
+        assert_eq!(death_reason(&exit(CROSVM_REBOOT_STATUS), ""), DeathReason::REBOOT);
+        assert_eq!(death_reason(&exit(CROSVM_CRASH_STATUS), ""), DeathReason::CRASH);
+        assert_eq!(death_reason(&signal(libc::SIGKILL), ""), DeathReason::KILLED);
+        assert_eq!(death_reason(&signal(libc::SIGTERM), ""), DeathReason::KILLED);
+        assert_eq!(death_reason(&signal(libc::SIGINT), ""), DeathReason::KILLED);
+        assert_eq!(death_reason(&signal(libc::SIGSEGV), ""), DeathReason::CRASH);
+        assert_eq!(death_reason(&signal(libc::SIGKILL), "HANGUP"), DeathReason::HANGUP);
+        assert_eq!(
+            death_reason(&exit(1), "MICRODROID_PAYLOAD_HAS_CHANGED|some info"),
+            DeathReason::MICRODROID_PAYLOAD_HAS_CHANGED
+        );
+    }
+
+    #[test]
+    fn parse_cpu_affinity_global() -> Result<()> {
+        assert_eq!(parse_cpu_affinity("0,2-4")?, (vec![], vec![0, 2, 3, 4]));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_cpu_affinity_per_vcpu() -> Result<()> {
+        assert_eq!(parse_cpu_affinity("0=0:1=1,3-4")?, (vec![0, 1], vec![0, 1, 3, 4]));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_cpu_affinity_invalid() {
+        assert!(parse_cpu_affinity("").is_err());
+        assert!(parse_cpu_affinity("3-1").is_err());
+        assert!(parse_cpu_affinity("0=0:1").is_err());
+        assert!(parse_cpu_affinity("a=0").is_err());
+    }
+
+    #[test]
+    fn read_log_lines_calls_back_per_line() {
+        let lines = std::cell::RefCell::new(vec![]);
+        let mut log_fd = vec![];
+        let log = b"booting\r\npayload started\npartial";
+        read_log_lines(&log[..], Some(&mut log_fd), |line| {
+            lines.borrow_mut().push(line.to_owned())
+        });
+        assert_eq!(lines.into_inner(), ["booting", "payload started", "partial"]);
+        assert_eq!(log_fd, log);
+    }
+
+    #[test]
+    fn payload_milestone_from_log_line() {
+        assert_eq!(
+            payload_milestone(
+                "10-14 12:00:00.000  123  123 I microdroid_manager: notifying payload started"
+            ),
+            Some(PayloadState::Started)
+        );
+        assert_eq!(payload_milestone("I microdroid_manager: executing main task"), None);
+        assert_eq!(payload_milestone("I other: notifying payload started"), None);
+    }
+
+    #[test]
+    fn wait_fails_if_vm_failed_to_start() -> Result<()> {
+        let temporary_directory = tempfile::tempdir()?;
+        let mut config = test_config()?;
+        // The bootconfig can't be read, so starting the VM fails to append it to the initrd.
+        config.initrd = Some(tempfile::tempfile()?);
+        config.bootconfig = Some(File::create(temporary_directory.path().join("bootconfig"))?);
+        let context = test_context(temporary_directory.path())?;
+        let instance = Arc::new(VmInstance::new(
+            config,
+            temporary_directory.path().to_owned(),
+            0,
+            0,
+            context,
+        )?);
+
+        assert!(instance.start().is_err());
+        assert_eq!(instance.wait(None).unwrap_err(), WaitError::Failed);
+        Ok(())
+    }
+}
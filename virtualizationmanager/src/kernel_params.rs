@@ -0,0 +1,247 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Building of the kernel command line of a VM.
+
+use anyhow::{ensure, Result};
+use std::fmt;
+
+/// The kernel command line of a VM, built up from individual parameters.
+///
+/// Parameters are rendered in the order in which they were first added. Setting a key which was
+/// already set replaces its value in place, so each key set through the builder appears at most
+/// once. Parameters from already formatted command lines are instead kept exactly as they were,
+/// including repeated keys such as `console=ttyS0 console=hvc0`, and so are the arguments for init
+/// after a `--`, which are always rendered last.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct KernelParams {
+    params: Vec<Param>,
+    init_args: Option<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Param {
+    /// A parameter set through the builder, with its value unquoted.
+    Set(String, Option<String>),
+    /// A parameter from a formatted command line, as it was written.
+    Verbatim(String),
+}
+
+impl KernelParams {
+    /// Creates an empty kernel command line.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the parameter `key` to `value`. The value shouldn't be quoted, as that is done when
+    /// rendering if needed.
+    ///
+    /// Fails if `value` contains a double quote, as the kernel has no way to escape it.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<&mut Self> {
+        ensure!(!value.contains('"'), "Kernel parameter {key} has a '\"' in its value: {value}");
+        Ok(self.insert(key, Some(value)))
+    }
+
+    /// Adds a parameter without a value, e.g. `quiet`.
+    pub fn flag(&mut self, key: &str) -> &mut Self {
+        self.insert(key, None)
+    }
+
+    /// Adds all the parameters of an already formatted command line, such as `foo=1 bar="a b"`,
+    /// without changing them.
+    pub fn extend_from_str(&mut self, params: &str) -> &mut Self {
+        let (params, init_args) = split_params(params);
+        self.params.extend(params.into_iter().map(|param| Param::Verbatim(param.to_owned())));
+        if let Some(init_args) = init_args {
+            self.add_init_args(init_args);
+        }
+        self
+    }
+
+    /// Adds all the parameters of `other` after those already present, replacing the values of
+    /// the keys which were set on both.
+    pub fn extend(&mut self, other: &KernelParams) -> &mut Self {
+        for param in &other.params {
+            match param {
+                Param::Set(key, value) => {
+                    self.insert(key, value.as_deref());
+                }
+                Param::Verbatim(_) => self.params.push(param.clone()),
+            }
+        }
+        if let Some(init_args) = &other.init_args {
+            self.add_init_args(init_args);
+        }
+        self
+    }
+
+    /// Returns whether there are no parameters.
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty() && self.init_args.is_none()
+    }
+
+    fn insert(&mut self, key: &str, value: Option<&str>) -> &mut Self {
+        let value = value.map(str::to_owned);
+        let existing = self.params.iter_mut().find_map(|param| match param {
+            Param::Set(k, v) if k == key => Some(v),
+            _ => None,
+        });
+        if let Some(existing) = existing {
+            *existing = value;
+        } else {
+            self.params.push(Param::Set(key.to_owned(), value));
+        }
+        self
+    }
+
+    fn add_init_args(&mut self, init_args: &str) {
+        let existing = self.init_args.get_or_insert_with(String::new);
+        if !existing.is_empty() && !init_args.is_empty() {
+            existing.push(' ');
+        }
+        existing.push_str(init_args);
+    }
+}
+
+impl From<&str> for KernelParams {
+    fn from(params: &str) -> Self {
+        let mut kernel_params = Self::new();
+        kernel_params.extend_from_str(params);
+        kernel_params
+    }
+}
+
+impl fmt::Display for KernelParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, param) in self.params.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            match param {
+                Param::Set(key, Some(value)) if value.contains(char::is_whitespace) => {
+                    write!(f, "{key}=\"{value}\"")?
+                }
+                Param::Set(key, Some(value)) => write!(f, "{key}={value}")?,
+                Param::Set(key, None) => f.write_str(key)?,
+                Param::Verbatim(param) => f.write_str(param)?,
+            }
+        }
+        if let Some(init_args) = &self.init_args {
+            if !self.params.is_empty() {
+                f.write_str(" ")?;
+            }
+            f.write_str("--")?;
+            if !init_args.is_empty() {
+                write!(f, " {init_args}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits a command line on whitespace, except for whitespace within double quotes, up to a `--`.
+/// Returns the parameters before the `--`, and the rest of the command line after it if any.
+fn split_params(params: &str) -> (Vec<&str>, Option<&str>) {
+    let mut result = vec![];
+    let mut in_quotes = false;
+    let mut start = None;
+    for (i, c) in params.char_indices().chain([(params.len(), ' ')]) {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        }
+        if (!in_quotes || i == params.len()) && c.is_whitespace() {
+            if let Some(start) = start.take() {
+                match &params[start..i] {
+                    "--" => return (result, Some(params[i..].trim())),
+                    param => result.push(param),
+                }
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    (result, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_insertion_order() -> Result<()> {
+        let mut params = KernelParams::new();
+        params.set("console", "hvc0")?.flag("quiet").set("panic", "-1")?;
+        assert_eq!(params.to_string(), "console=hvc0 quiet panic=-1");
+        Ok(())
+    }
+
+    #[test]
+    fn replaces_duplicate_keys_in_place() -> Result<()> {
+        let mut params = KernelParams::new();
+        params.set("console", "ttyS0")?.flag("quiet").set("foo", "1")?.set("console", "hvc0")?;
+        assert_eq!(params.to_string(), "console=hvc0 quiet foo=1");
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_repeated_keys_from_strings() -> Result<()> {
+        let mut params = KernelParams::from("console=ttyS0 console=hvc0");
+        params.set("console", "ttyAMA0")?;
+        assert_eq!(params.to_string(), "console=ttyS0 console=hvc0 console=ttyAMA0");
+        Ok(())
+    }
+
+    #[test]
+    fn extend_appends_after_existing_params() -> Result<()> {
+        let mut params = KernelParams::new();
+        params.set("console", "hvc0")?.flag("bootconfig");
+        let mut other = KernelParams::from("foo=1 console=ttyS0");
+        other.flag("bootconfig");
+        params.extend(&other);
+        assert_eq!(params.to_string(), "console=hvc0 bootconfig foo=1 console=ttyS0");
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_init_args_unchanged_and_last() -> Result<()> {
+        let mut params = KernelParams::from("foo=1 -- a=1 a=2  \"b c\"");
+        params.flag("bootconfig");
+        assert_eq!(params.to_string(), "foo=1 bootconfig -- a=1 a=2  \"b c\"");
+        params.extend(&KernelParams::from("bar -- d"));
+        assert_eq!(params.to_string(), "foo=1 bootconfig bar -- a=1 a=2  \"b c\" d");
+        assert_eq!(KernelParams::from("--").to_string(), "--");
+        Ok(())
+    }
+
+    #[test]
+    fn quotes_values_once() -> Result<()> {
+        let mut params = KernelParams::from("init=\"/bin/sh -x\"");
+        params.set("foo", "a b")?;
+        assert_eq!(params.to_string(), "init=\"/bin/sh -x\" foo=\"a b\"");
+        assert_eq!(KernelParams::from(params.to_string().as_str()).to_string(), params.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_values_with_quotes() {
+        assert!(KernelParams::new().set("foo", "a\"b").is_err());
+    }
+
+    #[test]
+    fn empty() {
+        assert!(KernelParams::from("  ").is_empty());
+        assert!(!KernelParams::from("--").is_empty());
+        assert_eq!(KernelParams::new().to_string(), "");
+    }
+}
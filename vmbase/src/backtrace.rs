@@ -0,0 +1,52 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Raw backtraces of the current stack, for debugging.
+
+use core::arch::asm;
+use core::fmt::{self, Write};
+
+/// Maximum number of frames written, in case the frame records are corrupted into a long chain.
+const MAX_FRAMES: usize = 32;
+
+/// Writes the return addresses found by walking the chain of AArch64 frame records, one per line.
+///
+/// This relies on the code being built with frame pointers and stops at the first frame record
+/// which doesn't look valid. The addresses aren't symbolized, which can be done offline from the
+/// unstripped ELF, e.g. with `llvm-addr2line`.
+#[inline(never)]
+pub(crate) fn write_backtrace(w: &mut (impl Write + ?Sized)) -> fmt::Result {
+    let mut fp: usize;
+    // SAFETY: Reading the frame pointer doesn't affect memory.
+    unsafe { asm!("mov {}, x29", out(reg) fp, options(nomem, nostack, preserves_flags)) };
+    for frame in 0..MAX_FRAMES {
+        if fp == 0 || fp % 16 != 0 {
+            break;
+        }
+        let record = fp as *const usize;
+        // SAFETY: With frame pointers, x29 points to a frame record made of the previous frame
+        // pointer and the return address, and each frame record points to the previous one.
+        let (next_fp, lr) = unsafe { (record.read(), record.add(1).read()) };
+        if lr == 0 {
+            break;
+        }
+        writeln!(w, "  #{frame:<2} {lr:#018x}")?;
+        // The stack grows down, so anything else means that the chain is corrupted.
+        if next_fp <= fp {
+            break;
+        }
+        fp = next_fp;
+    }
+    Ok(())
+}
@@ -14,14 +14,73 @@
 
 //! Console driver for 8250 UART.
 
-use crate::uart::Uart;
+use crate::pl011::Pl011;
+use crate::read_sysreg;
+use crate::uart::{RxBuffer, Uart};
+use core::arch::asm;
 use core::fmt::{write, Arguments, Write};
 use spin::mutex::SpinMutex;
 
 /// Base memory-mapped address of the primary UART device.
 pub const BASE_ADDRESS: usize = 0x3f8;
 
+/// Number of received bytes which can be buffered until they are read with [`read_byte`].
+const RX_BUFFER_LEN: usize = 256;
+
 static CONSOLE: SpinMutex<Option<Uart>> = SpinMutex::new(None);
+static RX_BUFFER: SpinMutex<RxBuffer<RX_BUFFER_LEN>> = SpinMutex::new(RxBuffer::new());
+
+/// A serial device which can be used as the console, independently of the UART behind it.
+pub trait Console: Write {
+    /// Writes a single byte to the device, even if it doesn't report being ready in time.
+    ///
+    /// Unlike the inherent [`Uart::write_byte`], this can't fail, for callers which can't handle
+    /// errors. Calling `write_byte` on a `Uart` resolves to the inherent method, so use
+    /// `Console::write_byte` to call this one.
+    fn write_byte(&self, byte: u8);
+
+    /// Reads a single byte from the device, or returns `None` if no byte has been received.
+    fn read_byte(&self) -> Option<u8>;
+}
+
+impl Console for Uart {
+    fn write_byte(&self, byte: u8) {
+        self.write_bytes_ignoring_timeouts(&[byte])
+    }
+
+    fn read_byte(&self) -> Option<u8> {
+        Uart::read_byte(self)
+    }
+}
+
+impl Console for Pl011 {
+    fn write_byte(&self, byte: u8) {
+        Pl011::write_byte(self, byte)
+    }
+
+    fn read_byte(&self) -> Option<u8> {
+        Pl011::read_byte(self)
+    }
+}
+
+/// A console which discards everything written to it and never receives anything, for code
+/// which needs a `Console` when no UART is available.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullUart;
+
+impl Write for NullUart {
+    fn write_str(&mut self, _s: &str) -> core::fmt::Result {
+        Ok(())
+    }
+}
+
+impl Console for NullUart {
+    fn write_byte(&self, _byte: u8) {}
+
+    fn read_byte(&self) -> Option<u8> {
+        None
+    }
+}
 
 /// Initialises a new instance of the UART driver and returns it.
 fn create() -> Uart {
@@ -34,21 +93,94 @@ fn create() -> Uart {
 /// the `print!` and `println!` macros.
 pub fn init() {
     let uart = create();
-    CONSOLE.lock().replace(uart);
+    uart.init();
+    with_irqs_masked(|| CONSOLE.lock().replace(uart));
 }
 
 /// Writes a string to the console.
 ///
 /// Panics if [`init`] was not called first.
 pub(crate) fn write_str(s: &str) {
-    CONSOLE.lock().as_mut().unwrap().write_str(s).unwrap();
+    with_console(|console| console.write_str(s).unwrap());
+}
+
+/// Writes a formatted string followed by a newline to the console, without letting other
+/// writers interleave with it.
+///
+/// Panics if [`init`] was not called first.
+pub(crate) fn write_line(format_args: Arguments) {
+    with_console(|console| {
+        console.write_fmt(format_args).unwrap();
+        console.write_str("\n").unwrap();
+    });
 }
 
-/// Writes a formatted string to the console.
+/// Enables the interrupt raised by the console UART when it receives a byte.
+///
+/// Once enabled, the interrupt must be routed to [`handle_rx_interrupt`] and the received bytes
+/// can be read with [`read_byte`].
+///
+/// Panics if [`init`] was not called first.
+pub fn enable_rx_interrupt() {
+    with_uart(|uart| uart.set_rx_interrupt(true));
+}
+
+/// Moves the bytes received by the console UART to the buffer read by [`read_byte`].
+///
+/// This is the hook for the IRQ handler to call when the interrupt controller signals the
+/// interrupt of the console UART. The caller remains responsible for acknowledging the interrupt
+/// to the interrupt controller.
+pub fn handle_rx_interrupt() {
+    // Don't take the global instance, which the interrupted code may be holding.
+    let uart = create();
+    uart.drain_rx(&mut *RX_BUFFER.lock());
+}
+
+/// Returns the oldest byte received by the console UART since [`enable_rx_interrupt`], if any.
+pub fn read_byte() -> Option<u8> {
+    with_irqs_masked(|| RX_BUFFER.lock().pop())
+}
+
+/// Runs `f` with exclusive access to the global console, so that writes from different cores are
+/// serialized.
+///
+/// IRQs are masked while the lock is held, so that an IRQ handler using the console can't
+/// deadlock with the code it interrupted.
 ///
 /// Panics if [`init`] was not called first.
-pub(crate) fn write_args(format_args: Arguments) {
-    write(CONSOLE.lock().as_mut().unwrap(), format_args).unwrap();
+fn with_console<T>(f: impl FnOnce(&mut dyn Console) -> T) -> T {
+    with_uart(|uart| f(uart))
+}
+
+/// Runs `f` with exclusive access to the global instance of the UART driver like
+/// [`with_console`], for the operations which are specific to the 8250.
+fn with_uart<T>(f: impl FnOnce(&mut Uart) -> T) -> T {
+    with_irqs_masked(|| f(CONSOLE.lock().as_mut().unwrap()))
+}
+
+/// Runs `f` with the global console like [`with_console`], so that its output isn't interleaved
+/// with other writers, but falls back to a new instance of the UART driver if the global one
+/// isn't initialized or is locked, e.g. because the current core panicked while holding it.
+pub(crate) fn with_console_or_new<T>(f: impl FnOnce(&mut dyn Console) -> T) -> T {
+    with_irqs_masked(|| {
+        let mut console = CONSOLE.try_lock();
+        match console.as_deref_mut().and_then(Option::as_mut) {
+            Some(uart) => f(uart),
+            None => f(&mut create()),
+        }
+    })
+}
+
+/// Runs `f` with IRQs masked on the current core, so that an IRQ handler can't deadlock on a lock
+/// held by `f`.
+pub(crate) fn with_irqs_masked<T>(f: impl FnOnce() -> T) -> T {
+    let daif = read_sysreg!("daif");
+    // SAFETY: Masking IRQs doesn't affect memory.
+    unsafe { asm!("msr daifset, #2", options(nostack)) };
+    let result = f();
+    // SAFETY: This restores the IRQ mask of the current core to its previous value.
+    unsafe { asm!("msr daif, {}", in(reg) daif, options(nostack)) };
+    result
 }
 
 /// Reinitializes the UART driver and writes a string to it.
@@ -75,10 +207,7 @@ pub fn emergency_write_args(format_args: Arguments) {
 /// use `eprintln!` instead.
 macro_rules! println {
     () => ($crate::console::write_str("\n"));
-    ($($arg:tt)*) => ({
-        $crate::console::write_args(format_args!($($arg)*))};
-        $crate::console::write_str("\n");
-    );
+    ($($arg:tt)*) => ($crate::console::write_line(format_args!($($arg)*)));
 }
 
 pub(crate) use println; // Make it available in this crate.
@@ -88,7 +88,7 @@ pub fn scratch_range() -> Range<VirtualAddress> {
 
 /// UART console range.
 pub fn console_uart_range() -> Range<VirtualAddress> {
-    const CONSOLE_LEN: usize = 1; // `uart::Uart` only uses one u8 register.
+    const CONSOLE_LEN: usize = 8; // `uart::Uart` uses the 8 u8 registers of the 8250.
 
     VirtualAddress(BASE_ADDRESS)..VirtualAddress(BASE_ADDRESS + CONSOLE_LEN)
 }
@@ -19,6 +19,7 @@
 extern crate alloc;
 
 pub mod arch;
+mod backtrace;
 pub mod bionic;
 pub mod console;
 mod entry;
@@ -31,17 +32,23 @@ pub mod layout;
 pub mod linker;
 pub mod logger;
 pub mod memory;
+pub mod pl011;
 pub mod power;
 pub mod rand;
 pub mod uart;
 pub mod util;
 pub mod virtio;
 
+use core::fmt::Write;
 use core::panic::PanicInfo;
 use power::reboot;
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    eprintln!("{}", info);
+    console::with_console_or_new(|console| {
+        let _ = writeln!(console, "{info}");
+        let _ = writeln!(console, "Backtrace:");
+        let _ = backtrace::write_backtrace(console);
+    });
     reboot()
 }
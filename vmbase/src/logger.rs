@@ -14,13 +14,17 @@
 
 //! Logger for vmbase.
 //!
-//! Internally uses the println! vmbase macro, which prints to crosvm's UART.
+//! Internally uses the println! vmbase macro, which prints to crosvm's UART, unless another
+//! console was given to [`init_logger`]. Each record is written as a single line while holding the
+//! console lock, so that records logged concurrently don't interleave.
 //! Note: may not work if the VM is in an inconsistent state. Exception handlers
 //! should avoid using this logger and instead print with eprintln!.
 
-use crate::console::println;
+use crate::console::{println, with_irqs_masked, Console};
+use core::fmt::Write;
 use core::sync::atomic::{AtomicBool, Ordering};
-use log::{Log, Metadata, Record, SetLoggerError};
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use spin::mutex::SpinMutex;
 
 struct Logger {
     is_enabled: AtomicBool,
@@ -28,6 +32,9 @@ struct Logger {
 
 static LOGGER: Logger = Logger::new();
 
+/// The console to write records to instead of the global console, if any.
+static CONSOLE: SpinMutex<Option<&'static mut (dyn Console + Send)>> = SpinMutex::new(None);
+
 impl Logger {
     const fn new() -> Self {
         Self { is_enabled: AtomicBool::new(true) }
@@ -44,7 +51,16 @@ impl Log for Logger {
     }
 
     fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let written = with_irqs_masked(|| {
+            let mut console = CONSOLE.lock();
+            let console = console.as_deref_mut()?;
+            let _ = writeln!(console, "[{}] {}", record.level(), record.args());
+            Some(())
+        });
+        if written.is_none() {
             println!("[{}] {}", record.level(), record.args());
         }
     }
@@ -75,6 +91,12 @@ pub(crate) fn init() -> Result<(), SetLoggerError> {
     Ok(())
 }
 
+/// Makes the logger write records up to `level` to `console`, rather than to the global console.
+pub fn init_logger(console: &'static mut (dyn Console + Send), level: LevelFilter) {
+    with_irqs_masked(|| CONSOLE.lock().replace(console));
+    log::set_max_level(level);
+}
+
 /// Suppress logging until the return value goes out of scope.
 pub fn suppress() -> SuppressGuard {
     SuppressGuard::new()
@@ -0,0 +1,102 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal driver for an ARM PrimeCell UART (PL011), as found on many ARM platforms.
+
+use core::fmt::{self, Write};
+use core::hint::spin_loop;
+
+/// Offset of the Data Register.
+const DR_OFFSET: usize = 0x00;
+/// Offset of the Flag Register.
+const FR_OFFSET: usize = 0x18;
+
+/// FR bit set when the receive FIFO is empty.
+const FR_RXFE: u32 = 1 << 4;
+/// FR bit set when the transmit FIFO is full.
+const FR_TXFF: u32 = 1 << 5;
+
+/// Maximum number of times the FR is polled for the transmit FIFO to have room before writing
+/// anyway, so that a misconfigured UART can't hang the caller.
+const TX_READY_MAX_SPINS: usize = 100_000;
+
+/// Minimal driver for a PL011 UART, which assumes that the UART has already been configured and
+/// enabled, e.g. by the bootloader.
+pub struct Pl011 {
+    base_address: *mut u8,
+}
+
+impl Pl011 {
+    /// Constructs a new instance of the UART driver for a device at the given base address.
+    ///
+    /// # Safety
+    ///
+    /// The given base address must point to the MMIO control registers of a PL011 device, which
+    /// must be mapped into the address space of the process as device memory and not have any
+    /// other aliases.
+    pub unsafe fn new(base_address: usize) -> Self {
+        Self { base_address: base_address as *mut u8 }
+    }
+
+    /// Writes a single byte to the UART.
+    ///
+    /// Waits for the transmit FIFO to have room first, but writes the byte anyway if it doesn't
+    /// after a bounded number of polls.
+    pub fn write_byte(&self, byte: u8) {
+        for _ in 0..TX_READY_MAX_SPINS {
+            if self.read_register(FR_OFFSET) & FR_TXFF == 0 {
+                break;
+            }
+            spin_loop();
+        }
+        self.write_register(DR_OFFSET, byte.into());
+    }
+
+    /// Reads a single byte from the UART, or returns `None` if no byte has been received.
+    pub fn read_byte(&self) -> Option<u8> {
+        if self.read_register(FR_OFFSET) & FR_RXFE != 0 {
+            None
+        } else {
+            // The upper bits of the DR hold the error flags of the received byte.
+            Some(self.read_register(DR_OFFSET) as u8)
+        }
+    }
+
+    fn read_register(&self, offset: usize) -> u32 {
+        // SAFETY: We know that the base address points to the control registers of a PL011
+        // device which is appropriately mapped, and `offset` is the offset of one of these 32-bit
+        // registers.
+        unsafe { self.base_address.add(offset).cast::<u32>().read_volatile() }
+    }
+
+    fn write_register(&self, offset: usize, value: u32) {
+        // SAFETY: We know that the base address points to the control registers of a PL011
+        // device which is appropriately mapped, and `offset` is the offset of one of these 32-bit
+        // registers.
+        unsafe { self.base_address.add(offset).cast::<u32>().write_volatile(value) }
+    }
+}
+
+impl Write for Pl011 {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.as_bytes() {
+            self.write_byte(*c);
+        }
+        Ok(())
+    }
+}
+
+// SAFETY: `Pl011` just contains a pointer to device memory, which can be accessed from any
+// context.
+unsafe impl Send for Pl011 {}
@@ -13,6 +13,9 @@
 // limitations under the License.
 
 //! Functions for shutting down the VM.
+//!
+//! These use PSCI through the `smccc` crate, with the HVC conduit as the calls are handled by the
+//! hypervisor. crosvm then exits, with a status showing whether the VM was shut down or reset.
 
 use smccc::{
     psci::{system_off, system_reset},
@@ -15,12 +15,132 @@
 //! Minimal driver for an 8250 UART. This only implements enough to work with the emulated 8250
 //! provided by crosvm, and won't work with real hardware.
 
+use core::cell::Cell;
 use core::fmt::{self, Write};
+use core::hint::spin_loop;
+
+/// Offset of the Receiver Buffer Register, when read.
+const RBR_OFFSET: usize = 0;
+/// Offset of the Transmitter Holding Register, when written.
+const THR_OFFSET: usize = 0;
+/// Offset of the Interrupt Enable Register.
+const IER_OFFSET: usize = 1;
+/// Offset of the low byte of the Divisor Latch, when LCR_DLAB is set.
+const DLL_OFFSET: usize = 0;
+/// Offset of the high byte of the Divisor Latch, when LCR_DLAB is set.
+const DLM_OFFSET: usize = 1;
+/// Offset of the FIFO Control Register, when written.
+const FCR_OFFSET: usize = 2;
+/// Offset of the Line Control Register.
+const LCR_OFFSET: usize = 3;
+/// Offset of the Line Status Register.
+const LSR_OFFSET: usize = 5;
+
+/// IER bit enabling the Received Data Available interrupt.
+const IER_RX_DATA_AVAILABLE: u8 = 1 << 0;
+
+/// FCR bit enabling the RX and TX FIFOs.
+const FCR_FIFO_ENABLE: u8 = 1 << 0;
+/// FCR bit clearing the RX FIFO.
+const FCR_CLEAR_RX: u8 = 1 << 1;
+/// FCR bit clearing the TX FIFO.
+const FCR_CLEAR_TX: u8 = 1 << 2;
+/// FCR value of the RX FIFO trigger level for 8 bytes.
+const FCR_RX_TRIGGER_8: u8 = 0b10 << 6;
+
+/// LCR value for 8 data bits, no parity and 1 stop bit.
+const LCR_8N1: u8 = 0b11;
+/// LCR bit forcing the serial output to the spacing state, which sends a break.
+const LCR_BREAK: u8 = 1 << 6;
+/// LCR bit giving access to the Divisor Latch instead of the RBR/THR and IER.
+const LCR_DLAB: u8 = 1 << 7;
+
+/// LSR bit set when a received byte is available in the RBR.
+const LSR_DATA_READY: u8 = 1 << 0;
+/// LSR bit set when the THR is empty and can accept a new byte.
+const LSR_THR_EMPTY: u8 = 1 << 5;
+/// LSR bits reporting receive errors, which are exposed as [`LineStatus`].
+const LSR_ERRORS: u8 = 0b1111 << 1;
+
+/// Number of bytes which can be written to the THR once it is empty, without a FIFO.
+const TX_BURST_LEN: usize = 1;
+/// Number of bytes which can be written to the THR once it is empty, with the 16-byte FIFO.
+const TX_FIFO_BURST_LEN: usize = 16;
+
+/// Maximum number of times the LSR is polled for the THR to be empty before giving up, so that a
+/// misconfigured UART can't hang the caller.
+const TX_READY_MAX_SPINS: usize = 100_000;
+
+/// Errors of the 8250 UART driver.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UartError {
+    /// The transmitter didn't become ready to accept a byte in time.
+    TxTimeout,
+    /// The baud rate can't be generated from the clock of the UART.
+    InvalidBaudRate,
+    /// The UART reported errors while receiving.
+    RxError(LineStatus),
+}
+
+impl fmt::Display for UartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TxTimeout => write!(f, "Timed out waiting for the UART transmitter"),
+            Self::InvalidBaudRate => write!(f, "Unsupported baud rate for the UART clock"),
+            Self::RxError(status) => write!(f, "UART receive error: {status:?}"),
+        }
+    }
+}
+
+/// Error returned when the UART can't accept a byte without blocking.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct WouldBlock;
+
+impl fmt::Display for WouldBlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The UART transmitter isn't ready")
+    }
+}
+
+/// Receive errors reported by the Line Status Register of the UART.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LineStatus(u8);
+
+impl LineStatus {
+    /// A received byte was dropped because the RX FIFO was full.
+    pub const OVERRUN: Self = Self(1 << 1);
+    /// A received byte had the wrong parity.
+    pub const PARITY_ERROR: Self = Self(1 << 2);
+    /// A received byte didn't have a valid stop bit.
+    pub const FRAMING_ERROR: Self = Self(1 << 3);
+    /// A break condition was received.
+    pub const BREAK: Self = Self(1 << 4);
+
+    fn from_lsr(lsr: u8) -> Self {
+        Self(lsr & LSR_ERRORS)
+    }
+
+    /// Returns the raw LSR bits of the errors.
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns whether no error is reported.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns whether all the errors of `other` are reported.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
 
 /// Minimal driver for an 8250 UART. This only implements enough to work with the emulated 8250
 /// provided by crosvm, and won't work with real hardware.
 pub struct Uart {
     base_address: *mut u8,
+    tx_burst_len: Cell<usize>,
 }
 
 impl Uart {
@@ -32,28 +152,224 @@ impl Uart {
     /// device, which must be mapped into the address space of the process as device memory and not
     /// have any other aliases.
     pub unsafe fn new(base_address: usize) -> Self {
-        Self { base_address: base_address as *mut u8 }
+        Self { base_address: base_address as *mut u8, tx_burst_len: Cell::new(TX_BURST_LEN) }
+    }
+
+    /// Configures the UART for 8N1 and enables its FIFOs, after clearing them.
+    ///
+    /// This should be called once at bring-up, before anything is written. Calling it again
+    /// leaves the UART in the same configuration, but drops the bytes pending in the FIFOs.
+    pub fn init(&self) {
+        self.write_register(LCR_OFFSET, LCR_8N1);
+        self.write_register(
+            FCR_OFFSET,
+            FCR_FIFO_ENABLE | FCR_CLEAR_RX | FCR_CLEAR_TX | FCR_RX_TRIGGER_8,
+        );
+        self.tx_burst_len.set(TX_FIFO_BURST_LEN);
+    }
+
+    /// Programs the Divisor Latch so that the UART, clocked at `base_clock` Hz, runs at `baud`
+    /// bits per second.
+    ///
+    /// Fails if the resulting divisor is zero or doesn't fit in 16 bits.
+    pub fn set_baud_rate(&self, base_clock: u32, baud: u32) -> Result<(), UartError> {
+        let divisor = baud
+            .checked_mul(16)
+            .and_then(|d| base_clock.checked_div(d))
+            .filter(|&d| d != 0)
+            .and_then(|d| u16::try_from(d).ok())
+            .ok_or(UartError::InvalidBaudRate)?;
+        let [dll, dlm] = divisor.to_le_bytes();
+        let lcr = self.read_register(LCR_OFFSET);
+        self.write_register(LCR_OFFSET, lcr | LCR_DLAB);
+        self.write_register(DLL_OFFSET, dll);
+        self.write_register(DLM_OFFSET, dlm);
+        self.write_register(LCR_OFFSET, lcr);
+        Ok(())
+    }
+
+    /// Sends a break condition, by holding the serial output in the spacing state for
+    /// `duration_cycles` iterations of a spin loop.
+    ///
+    /// Bytes still being transmitted are corrupted by the break, so callers should flush them
+    /// first. Note that crosvm's 8250 emulation may ignore the break entirely.
+    pub fn send_break(&self, duration_cycles: u32) {
+        let lcr = self.read_register(LCR_OFFSET);
+        self.write_register(LCR_OFFSET, lcr | LCR_BREAK);
+        for _ in 0..duration_cycles {
+            spin_loop();
+        }
+        self.write_register(LCR_OFFSET, lcr & !LCR_BREAK);
     }
 
     /// Writes a single byte to the UART.
-    pub fn write_byte(&self, byte: u8) {
+    ///
+    /// Waits for the transmitter to be ready first, and fails without writing the byte if it
+    /// doesn't become ready after a bounded number of polls.
+    pub fn write_byte(&self, byte: u8) -> Result<(), UartError> {
+        self.write_bytes(&[byte])
+    }
+
+    /// Writes a single byte to the UART if the transmitter is ready for it, or returns
+    /// `WouldBlock` immediately otherwise, for callers which must not spin.
+    pub fn try_write_byte(&self, byte: u8) -> Result<(), WouldBlock> {
+        if self.read_register(LSR_OFFSET) & LSR_THR_EMPTY == 0 {
+            return Err(WouldBlock);
+        }
+        self.write_register(THR_OFFSET, byte);
+        Ok(())
+    }
+
+    /// Writes the given bytes to the UART.
+    ///
+    /// The LSR is only polled once for every burst of bytes that the transmitter can accept. If
+    /// it doesn't become ready after a bounded number of polls, this fails and the remaining
+    /// bytes aren't written.
+    pub fn write_bytes(&self, bytes: &[u8]) -> Result<(), UartError> {
+        for burst in bytes.chunks(self.tx_burst_len.get()) {
+            self.wait_for_tx_ready()?;
+            self.write_burst(burst);
+        }
+        Ok(())
+    }
+
+    /// Writes the given bytes to the UART like `write_bytes`, but writes them anyway if the
+    /// transmitter doesn't become ready in time, for callers which can't handle errors.
+    pub(crate) fn write_bytes_ignoring_timeouts(&self, bytes: &[u8]) {
+        for burst in bytes.chunks(self.tx_burst_len.get()) {
+            let _ = self.wait_for_tx_ready();
+            self.write_burst(burst);
+        }
+    }
+
+    fn write_burst(&self, burst: &[u8]) {
+        for byte in burst {
+            self.write_register(THR_OFFSET, *byte);
+        }
+    }
+
+    fn wait_for_tx_ready(&self) -> Result<(), UartError> {
+        for _ in 0..TX_READY_MAX_SPINS {
+            if self.read_register(LSR_OFFSET) & LSR_THR_EMPTY != 0 {
+                return Ok(());
+            }
+            spin_loop();
+        }
+        Err(UartError::TxTimeout)
+    }
+
+    /// Reads a single byte from the UART, or returns `None` if no byte has been received.
+    pub fn read_byte(&self) -> Option<u8> {
+        if self.read_register(LSR_OFFSET) & LSR_DATA_READY == 0 {
+            None
+        } else {
+            Some(self.read_register(RBR_OFFSET))
+        }
+    }
+
+    /// Reads a single byte from the UART like `read_byte`, but fails if the UART reported receive
+    /// errors since the LSR was last read.
+    ///
+    /// Reading the LSR clears its error bits, so the errors are reported only once. The received
+    /// byte, if any, is then left for the next read.
+    pub fn read_byte_checked(&self) -> Result<Option<u8>, UartError> {
+        let lsr = self.read_register(LSR_OFFSET);
+        let status = LineStatus::from_lsr(lsr);
+        if !status.is_empty() {
+            return Err(UartError::RxError(status));
+        }
+        Ok(if lsr & LSR_DATA_READY == 0 { None } else { Some(self.read_register(RBR_OFFSET)) })
+    }
+
+    /// Returns the receive errors reported by the UART since the LSR was last read.
+    ///
+    /// Reading the LSR clears its error bits, so the errors are reported only once.
+    pub fn line_status(&self) -> LineStatus {
+        LineStatus::from_lsr(self.read_register(LSR_OFFSET))
+    }
+
+    /// Enables or disables the interrupt raised when a byte has been received.
+    pub fn set_rx_interrupt(&self, enabled: bool) {
+        self.write_register(IER_OFFSET, if enabled { IER_RX_DATA_AVAILABLE } else { 0 });
+    }
+
+    /// Moves all the bytes received by the UART to `buffer`, which clears the Received Data
+    /// Available interrupt.
+    ///
+    /// Bytes which don't fit in `buffer` are dropped.
+    pub fn drain_rx<const N: usize>(&self, buffer: &mut RxBuffer<N>) {
+        while let Some(byte) = self.read_byte() {
+            buffer.push(byte);
+        }
+    }
+
+    /// Reads a single byte from the UART, spinning until one has been received.
+    pub fn read_byte_blocking(&self) -> u8 {
+        loop {
+            if let Some(byte) = self.read_byte() {
+                return byte;
+            }
+            spin_loop();
+        }
+    }
+
+    fn read_register(&self, offset: usize) -> u8 {
+        // SAFETY: We know that the base address points to the control registers of a UART device
+        // which is appropriately mapped, and `offset` is the offset of one of these registers.
+        unsafe { self.base_address.add(offset).read_volatile() }
+    }
+
+    fn write_register(&self, offset: usize, value: u8) {
         // SAFETY: We know that the base address points to the control registers of a UART device
-        // which is appropriately mapped.
-        unsafe {
-            core::arch::asm!(
-                "strb {value:w}, [{ptr}]",
-                value = in(reg) byte,
-                ptr = in(reg) self.base_address,
-            );
+        // which is appropriately mapped, and `offset` is the offset of one of these registers.
+        unsafe { self.base_address.add(offset).write_volatile(value) }
+    }
+}
+
+/// Fixed-size ring buffer of bytes received by a UART, which doesn't need a heap.
+pub struct RxBuffer<const N: usize> {
+    bytes: [u8; N],
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> RxBuffer<N> {
+    /// Creates an empty buffer.
+    pub const fn new() -> Self {
+        Self { bytes: [0; N], start: 0, len: 0 }
+    }
+
+    /// Appends `byte` to the buffer, or drops it and returns false if the buffer is full.
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.bytes[(self.start + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    /// Removes the oldest byte from the buffer and returns it, if any.
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
         }
+        let byte = self.bytes[self.start];
+        self.start = (self.start + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+impl<const N: usize> Default for RxBuffer<N> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl Write for Uart {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.as_bytes() {
-            self.write_byte(*c);
-        }
+        self.write_bytes_ignoring_timeouts(s.as_bytes());
         Ok(())
     }
 }
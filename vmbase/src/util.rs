@@ -14,8 +14,12 @@
 
 //! Utility functions.
 
+use core::fmt::{self, Write};
 use core::ops::Range;
 
+/// Default number of bytes printed on each line by [`hexdump`].
+pub const HEXDUMP_DEFAULT_WIDTH: usize = 16;
+
 /// Flatten [[T; N]] into &[T]
 /// TODO: use slice::flatten when it graduates from experimental
 pub fn flatten<T, const N: usize>(original: &[[T; N]]) -> &[T] {
@@ -91,3 +95,37 @@ impl<T: PartialOrd> RangeExt for Range<T> {
         self.start < other.end && other.start < self.end
     }
 }
+
+/// Writes `bytes`, located at `addr`, to `console` as lines of [`HEXDUMP_DEFAULT_WIDTH`] bytes.
+///
+/// Each line holds the address of its first byte, the bytes in hex and their ASCII characters,
+/// with '.' for the non-printable ones. This doesn't need a heap, so can be used in early boot.
+pub fn hexdump(console: &mut dyn Write, addr: usize, bytes: &[u8]) -> fmt::Result {
+    hexdump_with_width(console, addr, bytes, HEXDUMP_DEFAULT_WIDTH)
+}
+
+/// Same as [`hexdump`] but with `width` bytes on each line.
+pub fn hexdump_with_width(
+    console: &mut dyn Write,
+    addr: usize,
+    bytes: &[u8],
+    width: usize,
+) -> fmt::Result {
+    let width = width.max(1);
+    for (i, line) in bytes.chunks(width).enumerate() {
+        write!(console, "{:#018x}:", addr.wrapping_add(i * width))?;
+        for byte in line {
+            write!(console, " {byte:02x}")?;
+        }
+        for _ in line.len()..width {
+            console.write_str("   ")?;
+        }
+        console.write_str("  |")?;
+        for &byte in line {
+            let c = if byte.is_ascii_graphic() || byte == b' ' { byte.into() } else { '.' };
+            console.write_char(c)?;
+        }
+        console.write_str("|\n")?;
+    }
+    Ok(())
+}